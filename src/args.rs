@@ -1,23 +1,667 @@
-use crate::commands;
-use commands::Command;
+use crate::chunk_type::ChunkType;
+use crate::commands::Command;
+use crate::output::OutputArgs;
+use crate::png::Position;
+use crate::strip::StripPreset;
+
+use std::str::FromStr;
 
 use clap::Parser;
 
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    #[arg()]
+    #[command(subcommand)]
     pub command: Command,
 
-    #[arg()]
-    pub file_path: Option<String>,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+// rejects malformed chunk types before any file is touched
+fn parse_chunk_type(s: &str) -> Result<String, String> {
+    let chunk_type = ChunkType::from_str(s).map_err(|e| e.to_string())?;
+    if !chunk_type.is_valid() {
+        return Err(format!("'{}' is not a valid chunk type", s));
+    }
+    Ok(s.to_string())
+}
+
+// more files to run the same command on, one after another in parallel
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct BatchArgs {
+    /// Another PNG file, directory or glob to process the same way, may be repeated
+    #[arg(short = 'i', long = "input", value_name = "PATH")]
+    pub more_paths: Vec<String>,
+
+    /// Include PNG files in subdirectories of any directory given
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Number of files to process at once. Defaults to one per CPU, or one when decrypting with a passphrase
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub jobs: Option<u64>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct InputArgs {
+    /// PNG file to read, or a directory or quoted glob of them
+    pub file_path: String,
+
+    /// Report CRC errors and trailing garbage instead of failing
+    #[arg(long)]
+    pub lenient: bool,
+
+    #[command(flatten)]
+    pub batch: BatchArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct WriteArgs {
+    /// Keep unknown unsafe-to-copy chunks when critical chunks change
+    #[arg(long)]
+    pub keep_unsafe: bool,
+
+    /// Drop any data after IEND when writing
+    #[arg(long)]
+    pub strip_trailing: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PayloadArgs {
+    /// Encode the contents of this file instead of a message
+    #[arg(long)]
+    pub file: Option<String>,
+
+    /// MIME type to record with an encoded file
+    #[arg(long, requires = "file")]
+    pub mime: Option<String>,
+
+    /// Do not record the encoded file's name
+    #[arg(long, requires = "file")]
+    pub no_name: bool,
+
+    /// Deflate the payload before encrypting and storing it
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Encrypt the payload with a key derived from this passphrase
+    #[arg(long, env = "PNGLEE_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: Option<String>,
+
+    /// Seal the payload to this public key file, may be repeated
+    #[arg(long, conflicts_with = "passphrase")]
+    pub recipient: Vec<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DecryptArgs {
+    /// Decrypt the payload with a key derived from this passphrase
+    #[arg(long, env = "PNGLEE_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: Option<String>,
+
+    /// Private key file used to open a sealed payload
+    #[arg(long)]
+    pub identity: Option<String>,
+
+    /// Write the decoded payload to this file
+    #[arg(long)]
+    pub out: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PixelArgs {
+    /// Low bits of each sample to use, 1 to 8
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    pub bits: u8,
+
+    /// Channels to use as letters from rgbya. Defaults to the colour channels
+    #[arg(long)]
+    pub channels: Option<String>,
+
+    /// Spread the payload over pixels in an order derived from this key
+    #[arg(long, env = "PNGLEE_LSB_KEY", hide_env_values = true)]
+    pub lsb_key: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct EncodeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Chunk type to store the payload in
+    #[arg(value_parser = parse_chunk_type)]
+    pub chunk_type: String,
+
+    /// Message to hide, unless --file is given
+    #[arg(
+        required_unless_present = "file",
+        conflicts_with_all = ["file", "mime", "no_name"]
+    )]
+    pub message: Option<String>,
+
+    /// Write to this file instead of changing the input
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    #[command(flatten)]
+    pub payload: PayloadArgs,
 
-    #[arg()]
+    /// Split the encoded payload into chunks of at most this many bytes
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub chunk_size: Option<u64>,
+
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DecodeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Chunk type the payload is stored in
+    #[arg(value_parser = parse_chunk_type)]
+    pub chunk_type: String,
+
+    #[command(flatten)]
+    pub decrypt: DecryptArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RemoveArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Chunk type to remove the first chunk of
+    #[arg(value_parser = parse_chunk_type)]
+    pub chunk_type: String,
+
+    /// Write to this file instead of changing the input
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PrintArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Write any data found after IEND to this file
+    #[arg(long)]
+    pub dump_trailing: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+}
+
+// picks one chunk, either the nth of a type or by position in the file
+#[derive(clap::Args, Debug, Clone)]
+pub struct SelectArgs {
+    /// Chunk type to select, unless --index is given
+    #[arg(
+        value_parser = parse_chunk_type,
+        required_unless_present = "index",
+        conflicts_with = "index"
+    )]
     pub chunk_type: Option<String>,
 
-    #[arg()]
+    /// Which chunk of that type to select, counting from 0
+    #[arg(short = 'n', long, default_value_t = 0)]
+    pub occurrence: usize,
+
+    /// Select the chunk at this position in the file, counting from 0
+    #[arg(long)]
+    pub index: Option<usize>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DumpArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub select: SelectArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExtractArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub select: SelectArgs,
+
+    /// Write the whole length, type, data and CRC record instead of the data
+    #[arg(long)]
+    pub record: bool,
+
+    /// Write to this file instead of stdout
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct InjectArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Type of the chunk to build from --data-file
+    #[arg(long = "type", value_parser = parse_chunk_type, requires = "data_file")]
+    pub chunk_type: Option<String>,
+
+    /// File holding the new chunk's data
+    #[arg(long, requires = "chunk_type")]
+    pub data_file: Option<String>,
+
+    /// File of serialized chunk records to insert, each with a valid CRC
+    #[arg(
+        long,
+        required_unless_present = "data_file",
+        conflicts_with_all = ["chunk_type", "data_file"]
+    )]
+    pub record_file: Option<String>,
+
+    /// after-ihdr, before-idat, after-idat, before-iend or a chunk index
+    #[arg(long, default_value = "before-iend", value_parser = clap::value_parser!(Position))]
+    pub position: Position,
+
+    /// Write to this file instead of changing the input
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CopyMetaArgs {
+    /// PNG file to copy chunks from
+    pub source_path: String,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Chunk types to copy, comma separated. Defaults to all safe-to-copy chunks
+    #[arg(long, value_delimiter = ',', value_parser = parse_chunk_type)]
+    pub types: Vec<String>,
+
+    /// Write to this file instead of changing the destination
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct StripArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Group of chunks to strip
+    #[arg(long, value_enum, required_unless_present = "remove")]
+    pub preset: Option<StripPreset>,
+
+    /// Chunk types to strip as well, comma separated
+    #[arg(long, value_delimiter = ',', value_parser = parse_chunk_type)]
+    pub remove: Vec<String>,
+
+    /// Chunk types to keep even if the preset would strip them, comma separated
+    #[arg(long, value_delimiter = ',', value_parser = parse_chunk_type)]
+    pub keep: Vec<String>,
+
+    /// Write to this file instead of changing the input
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DiffArgs {
+    /// PNG file to compare from
+    pub a: String,
+
+    /// PNG file to compare to
+    pub b: String,
+
+    /// Also decode both images and compare their pixels
+    #[arg(long)]
+    pub pixels: bool,
+
+    /// Report CRC errors and trailing garbage instead of failing
+    #[arg(long)]
+    pub lenient: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RepairArgs {
+    /// PNG file to repair, always read leniently, or a directory or quoted glob of them
+    pub file_path: String,
+
+    #[command(flatten)]
+    pub batch: BatchArgs,
+
+    /// Write to this file instead of changing the input
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    /// Keep any data after IEND instead of removing it
+    #[arg(long)]
+    pub keep_trailing: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct KeygenArgs {
+    /// Writes the private key to <KEY_PATH>.key and the public key to <KEY_PATH>.pub
+    pub key_path: String,
+
+    /// Generate an Ed25519 signing key instead of an encryption key
+    #[arg(long)]
+    pub signing: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SignArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Write to this file instead of changing the input
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    /// Private signing key file
+    #[arg(long)]
+    pub key: String,
+
+    /// Chunk types to sign, comma separated. Defaults to all critical chunks
+    #[arg(long, value_delimiter = ',', value_parser = parse_chunk_type)]
+    pub cover: Vec<String>,
+
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Public signing key file
+    #[arg(long)]
+    pub key: String,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HideArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Message to hide, unless --file is given
+    #[arg(
+        required_unless_present = "file",
+        conflicts_with_all = ["file", "mime", "no_name"]
+    )]
     pub message: Option<String>,
 
-    #[arg()]
-    pub output_file: Option<String>
+    /// Write to this file instead of changing the input
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+
+    #[command(flatten)]
+    pub payload: PayloadArgs,
+
+    #[command(flatten)]
+    pub pixels: PixelArgs,
+
+    #[command(flatten)]
+    pub write: WriteArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RevealArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub decrypt: DecryptArgs,
+
+    #[command(flatten)]
+    pub pixels: PixelArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CapacityArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub pixels: PixelArgs,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Format;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("pnglee").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_encode_args() {
+        let args = parse(&["encode", "in.png", "ruSt", "hello", "-o", "out.png"]).unwrap();
+        match args.command {
+            Command::Encode(encode) => {
+                assert_eq!(encode.input.file_path, "in.png");
+                assert_eq!(encode.chunk_type, "ruSt");
+                assert_eq!(encode.message.as_deref(), Some("hello"));
+                assert_eq!(encode.output_file.as_deref(), Some("out.png"));
+            }
+            other => panic!("Parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_output_args_are_global() {
+        let args = parse(&["print", "in.png"]).unwrap();
+        assert_eq!(args.output, OutputArgs::default());
+        let args = parse(&["print", "in.png", "--format", "ndjson"]).unwrap();
+        assert_eq!(args.output.format, Format::Ndjson);
+        let args = parse(&["--format", "json", "capacity", "in.png"]).unwrap();
+        assert_eq!(args.output.format, Format::Json);
+        assert!(parse(&["print", "in.png", "--format", "xml"]).is_err());
+
+        assert!(
+            parse(&["-q", "decode", "in.png", "ruSt"])
+                .unwrap()
+                .output
+                .quiet
+        );
+        assert!(
+            parse(&["decode", "in.png", "ruSt", "--quiet"])
+                .unwrap()
+                .output
+                .quiet
+        );
+    }
+
+    #[test]
+    fn test_missing_arguments() {
+        assert!(parse(&["encode", "in.png"]).is_err());
+        assert!(parse(&["encode", "in.png", "ruSt"]).is_err());
+        assert!(parse(&["remove", "in.png"]).is_err());
+        assert!(parse(&["sign", "in.png"]).is_err());
+        assert!(parse(&["sign", "in.png", "--key", "k", "--keep-unsafe"]).is_ok());
+        assert!(parse(&["frobnicate", "in.png"]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(parse(&["decode", "in.png", "ru1t"]).is_err());
+        assert!(parse(&["decode", "in.png", "toolong"]).is_err());
+        assert!(parse(&["capacity", "in.png", "--bits", "9"]).is_err());
+        assert!(parse(&["encode", "in.png", "ruSt", "hi", "--chunk-size", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_chunk_selection() {
+        let Command::Dump(dump) = parse(&["dump", "in.png", "tEXt", "-n", "2"])
+            .unwrap()
+            .command
+        else {
+            panic!("Not parsed as dump");
+        };
+        assert_eq!(dump.select.chunk_type.as_deref(), Some("tEXt"));
+        assert_eq!(dump.select.occurrence, 2);
+
+        let Command::Extract(extract) = parse(&["extract", "in.png", "--index", "3", "--record"])
+            .unwrap()
+            .command
+        else {
+            panic!("Not parsed as extract");
+        };
+        assert_eq!(extract.select.index, Some(3));
+        assert!(extract.record);
+
+        assert!(parse(&["dump", "in.png"]).is_err());
+        assert!(parse(&["dump", "in.png", "tEXt", "--index", "3"]).is_err());
+    }
+
+    #[test]
+    fn test_inject_args() {
+        let args = parse(&[
+            "inject",
+            "in.png",
+            "--type",
+            "abCd",
+            "--data-file",
+            "blob.bin",
+            "--position",
+            "after-ihdr",
+        ])
+        .unwrap();
+        let Command::Inject(inject) = args.command else {
+            panic!("Not parsed as inject");
+        };
+        assert_eq!(inject.chunk_type.as_deref(), Some("abCd"));
+        assert_eq!(inject.position, Position::AfterIhdr);
+
+        let args = parse(&["inject", "in.png", "--record-file", "r.bin"]).unwrap();
+        let Command::Inject(inject) = args.command else {
+            panic!("Not parsed as inject");
+        };
+        assert_eq!(inject.position, Position::BeforeIend);
+
+        assert!(parse(&["inject", "in.png"]).is_err());
+        assert!(parse(&["inject", "in.png", "--type", "abCd"]).is_err());
+        assert!(parse(&["inject", "in.png", "--record-file", "r", "--type", "abCd"]).is_err());
+        assert!(parse(&["inject", "in.png", "--record-file", "r", "--position", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_copy_meta_args() {
+        let args = parse(&[
+            "copy-meta",
+            "src.png",
+            "dst.png",
+            "--types",
+            "tEXt,pHYs",
+            "--keep-unsafe",
+        ])
+        .unwrap();
+        let Command::CopyMeta(copy) = args.command else {
+            panic!("Not parsed as copy-meta");
+        };
+        assert_eq!(copy.source_path, "src.png");
+        assert_eq!(copy.input.file_path, "dst.png");
+        assert_eq!(copy.types, vec!["tEXt", "pHYs"]);
+        assert!(copy.write.keep_unsafe);
+        assert!(parse(&["copy-meta", "src.png"]).is_err());
+    }
+
+    #[test]
+    fn test_strip_args() {
+        let args = parse(&[
+            "strip",
+            "in.png",
+            "--preset",
+            "keep-color",
+            "--keep",
+            "tEXt",
+            "--keep-unsafe",
+        ])
+        .unwrap();
+        let Command::Strip(strip) = args.command else {
+            panic!("Not parsed as strip");
+        };
+        assert_eq!(strip.preset, Some(StripPreset::KeepColor));
+        assert_eq!(strip.keep, vec!["tEXt"]);
+        assert!(strip.write.keep_unsafe);
+
+        assert!(parse(&["strip", "in.png", "--remove", "eXIf,tIME"]).is_ok());
+        assert!(parse(&["strip", "in.png"]).is_err());
+        assert!(parse(&["strip", "in.png", "--preset", "gps"]).is_err());
+    }
+
+    #[test]
+    fn test_diff_args() {
+        let args = parse(&["diff", "a.png", "b.png", "--pixels"]).unwrap();
+        let Command::Diff(diff) = args.command else {
+            panic!("Not parsed as diff");
+        };
+        assert_eq!((diff.a.as_str(), diff.b.as_str()), ("a.png", "b.png"));
+        assert!(diff.pixels);
+        assert!(parse(&["diff", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn test_batch_args() {
+        let args = parse(&[
+            "strip", "a.png", "--preset", "text", "-i", "b.png", "-i", "assets", "-r",
+        ])
+        .unwrap();
+        let Command::Strip(strip) = args.command else {
+            panic!("Not parsed as strip");
+        };
+        assert_eq!(strip.input.file_path, "a.png");
+        assert_eq!(strip.input.batch.more_paths, vec!["b.png", "assets"]);
+        assert!(strip.input.batch.recursive);
+
+        let args = parse(&["encode", "*.png", "ruSt", "hi", "--input", "x.png"]).unwrap();
+        let Command::Encode(encode) = args.command else {
+            panic!("Not parsed as encode");
+        };
+        assert_eq!(encode.message.as_deref(), Some("hi"));
+        assert_eq!(encode.input.batch.more_paths, vec!["x.png"]);
+
+        assert!(parse(&["repair", "dir", "-r", "--jobs", "4"]).is_ok());
+        assert!(parse(&["list", "a.png", "--jobs", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_arguments() {
+        assert!(parse(&["encode", "in.png", "ruSt", "hi", "--file", "a.bin"]).is_err());
+        assert!(parse(&["encode", "in.png", "ruSt", "hi", "--mime", "text/plain"]).is_err());
+        assert!(parse(&[
+            "encode",
+            "in.png",
+            "ruSt",
+            "hi",
+            "--passphrase",
+            "pw",
+            "--recipient",
+            "a.pub"
+        ])
+        .is_err());
+        assert!(parse(&["encode", "in.png", "ruSt", "--file", "a.bin", "-o", "b.png"]).is_ok());
+    }
 }
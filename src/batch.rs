@@ -0,0 +1,472 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::args::BatchArgs;
+use crate::commands::Command;
+use crate::output::{
+    emit, envelope, error_code, ErrorCode, Format, OutputArgs, Rendered, Report, WithCode,
+};
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+// a path that exists is taken as it is, even if it has glob characters
+// in it, e.g. "shot[1].png"
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '[']) && !Path::new(path).exists()
+}
+
+// a single plain file runs as it always has, without a summary
+pub fn is_batch(file_path: &str, batch: &BatchArgs) -> bool {
+    !batch.more_paths.is_empty() || is_glob(file_path) || Path::new(file_path).is_dir()
+}
+
+fn has_png_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+// files found for a batch, and the paths that could not be expanded,
+// which are reported as failures alongside the files' own
+#[derive(Debug, Default)]
+pub struct Expansion {
+    pub files: Vec<String>,
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+fn read_entries(dir: &Path) -> Result<Vec<(PathBuf, bool)>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| {
+            let entry = entry?;
+            Ok((entry.path(), entry.file_type()?.is_dir()))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+// symlinked directories are not followed, so a link cycle can't loop
+fn collect_dir(
+    dir: &Path,
+    recursive: bool,
+    files: &mut Vec<PathBuf>,
+    failed: &mut Vec<(String, anyhow::Error)>,
+) {
+    let entries = match read_entries(dir)
+        .with_context(|| format!("Could not read directory {}", dir.display()))
+    {
+        Ok(entries) => entries,
+        Err(error) => return failed.push((dir.display().to_string(), error)),
+    };
+
+    for (path, is_dir) in entries {
+        if is_dir {
+            if recursive {
+                collect_dir(&path, recursive, files, failed);
+            }
+        } else if has_png_extension(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn expand_glob(
+    pattern: &str,
+    recursive: bool,
+    files: &mut Vec<PathBuf>,
+    failed: &mut Vec<(String, anyhow::Error)>,
+) {
+    let matches = match glob::glob(pattern).with_code(ErrorCode::Usage) {
+        Ok(matches) => matches,
+        Err(error) => return failed.push((pattern.to_string(), error)),
+    };
+
+    let before = files.len();
+    for matched in matches {
+        match matched {
+            Ok(path) if path.is_dir() => collect_dir(&path, recursive, files, failed),
+            Ok(path) => files.push(path),
+            Err(error) => failed.push((error.path().display().to_string(), error.into())),
+        }
+    }
+    if files.len() == before {
+        let error: Result<()> =
+            Err(anyhow!("No PNG files match {}", pattern)).with_code(ErrorCode::NotFound);
+        failed.push((pattern.to_string(), error.unwrap_err()));
+    }
+}
+
+// directories give the PNG files in them, and globs whatever they match.
+// Plain paths are kept as they are, so a missing file fails on its own.
+// Paths are compared canonicalized, so "./a.png" and "a.png" are only run
+// once rather than written by two threads at the same time
+pub fn expand_paths(paths: &[String], recursive: bool) -> Expansion {
+    let mut files = vec![];
+    let mut failed = vec![];
+    for path in paths {
+        if is_glob(path) {
+            expand_glob(path, recursive, &mut files, &mut failed);
+        } else if Path::new(path).is_dir() {
+            collect_dir(Path::new(path), recursive, &mut files, &mut failed);
+        } else {
+            files.push(PathBuf::from(path));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut unique = vec![];
+    for file in files {
+        if !seen.insert(fs::canonicalize(&file).unwrap_or_else(|_| file.clone())) {
+            continue;
+        }
+        match file.to_str() {
+            Some(name) => unique.push(name.to_string()),
+            None => failed.push((
+                file.display().to_string(),
+                anyhow!("Path {} is not valid UTF-8", file.display()),
+            )),
+        }
+    }
+    Expansion {
+        files: unique,
+        failed,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedFile {
+    pub file: String,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchSummary {
+    pub files: usize,
+    pub succeeded: usize,
+    pub failed: Vec<FailedFile>,
+}
+
+impl BatchSummary {
+    pub fn new(results: &[(String, Result<Rendered>)]) -> BatchSummary {
+        let failed: Vec<FailedFile> = results
+            .iter()
+            .filter_map(|(file, result)| {
+                result.as_ref().err().map(|error| FailedFile {
+                    file: file.clone(),
+                    code: error_code(error),
+                    message: format!("{:#}", error),
+                })
+            })
+            .collect();
+        BatchSummary {
+            files: results.len(),
+            succeeded: results.len() - failed.len(),
+            failed,
+        }
+    }
+
+    // failures that all share a code keep it, a mix of them is Other
+    pub fn error(&self) -> Option<anyhow::Error> {
+        let first = self.failed.first()?;
+        let code = if self.failed.iter().all(|f| f.code == first.code) {
+            first.code
+        } else {
+            ErrorCode::Other
+        };
+        let error: Result<()> = Err(anyhow!(
+            "{} of {} files failed",
+            self.failed.len(),
+            self.files
+        ))
+        .with_code(code);
+        error.err()
+    }
+}
+
+impl Display for BatchSummary {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for failed in &self.failed {
+            writeln!(f, "Failed: {}: {}", failed.file, failed.message)?;
+        }
+        writeln!(
+            f,
+            "Processed {} files, {} succeeded, {} failed",
+            self.files,
+            self.succeeded,
+            self.failed.len()
+        )
+    }
+}
+
+impl Report for BatchSummary {}
+
+fn file_envelope(command: &str, file: &str, result: &Result<Rendered>) -> Value {
+    let mut value = envelope(command, result);
+    value["file"] = json!(file);
+    value
+}
+
+fn print_results(
+    output: &OutputArgs,
+    command: &str,
+    results: &[(String, Result<Rendered>)],
+    summary: &BatchSummary,
+) -> Result<()> {
+    match output.format {
+        Format::Text => {
+            for (file, result) in results {
+                match result {
+                    Ok(report) => match (report.raw(), output.quiet) {
+                        (Some(bytes), _) => io::stdout().write_all(bytes)?,
+                        (None, true) => {
+                            if let Some(quiet) = report.quiet() {
+                                println!("{}: {}", file, quiet);
+                            }
+                        }
+                        (None, false) => print!("==> {} <==\n{}", file, report),
+                    },
+                    Err(error) => eprintln!("Error: {}: {:#}", file, error),
+                }
+            }
+            if !output.quiet {
+                print!("{}", summary);
+            }
+        }
+        Format::Json => {
+            let files: Vec<Value> = results
+                .iter()
+                .map(|(file, result)| file_envelope(command, file, result))
+                .collect();
+            let value = json!({
+                "command": command,
+                "ok": summary.failed.is_empty(),
+                "files": files,
+                "summary": summary,
+            });
+            println!("{}", serde_json::to_string_pretty(&value)?);
+        }
+        // a line per file, then the summary
+        Format::Ndjson => {
+            for (file, result) in results {
+                println!("{}", file_envelope(command, file, result));
+            }
+            let value = json!({
+                "command": command,
+                "ok": summary.failed.is_empty(),
+                "summary": summary,
+            });
+            println!("{}", value);
+        }
+    }
+    Ok(())
+}
+
+fn run_files(
+    command: &Command,
+    files: &[String],
+    jobs: Option<u64>,
+) -> Result<Vec<(String, Result<Rendered>)>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0) as usize)
+        .build()?;
+    Ok(pool.install(|| {
+        files
+            .par_iter()
+            .map(|file| (file.clone(), command.with_input(file).run()))
+            .collect()
+    }))
+}
+
+// paths that could not be expanded come first, then every file found
+fn run_paths(
+    command: &Command,
+    paths: &[String],
+    batch: &BatchArgs,
+) -> Result<Vec<(String, Result<Rendered>)>> {
+    let expansion = expand_paths(paths, batch.recursive);
+    let mut results: Vec<(String, Result<Rendered>)> = expansion
+        .failed
+        .into_iter()
+        .map(|(path, error)| (path, Err(error)))
+        .collect();
+    let jobs = batch.jobs.or(command.derives_keys().then_some(1));
+    results.extend(run_files(command, &expansion.files, jobs)?);
+    Ok(results)
+}
+
+// runs the command on every file, carrying on past failures, and prints
+// each result in input order followed by a summary
+pub fn run(output: &OutputArgs, command: &Command) -> Result<()> {
+    let name = command.name();
+    let Some((file_path, batch)) = command.input() else {
+        return emit(output, name, command.clone().run());
+    };
+
+    if let Some(path) = command.fixed_output() {
+        let error = anyhow!(
+            "Cannot write every input to {}, leave it out to change files in place",
+            path
+        );
+        return emit::<Rendered>(output, name, Err(error).with_code(ErrorCode::Usage));
+    }
+
+    let paths: Vec<String> = std::iter::once(file_path.to_string())
+        .chain(batch.more_paths.iter().cloned())
+        .collect();
+    let results = match run_paths(command, &paths, batch) {
+        Ok(results) => results,
+        Err(error) => return emit::<Rendered>(output, name, Err(error)),
+    };
+
+    let summary = BatchSummary::new(&results);
+    print_results(output, name, &results, &summary)?;
+    match summary.error() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use clap::Parser;
+
+    fn temp_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("nested")).unwrap();
+        for file in ["b.png", "a.PNG", "notes.txt", "nested/c.png"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        root
+    }
+
+    fn names(files: &[String], root: &Path) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| {
+                Path::new(f)
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_is_batch() {
+        assert!(!is_batch("capy.png", &BatchArgs::default()));
+        assert!(is_batch("*.png", &BatchArgs::default()));
+        assert!(is_batch("src", &BatchArgs::default()));
+        let more = BatchArgs {
+            more_paths: vec!["other.png".to_string()],
+            ..Default::default()
+        };
+        assert!(is_batch("capy.png", &more));
+    }
+
+    #[test]
+    fn test_expand_paths() {
+        let root = temp_tree("pnglee-batch-expand");
+        let dir = root.to_str().unwrap().to_string();
+
+        let files = expand_paths(std::slice::from_ref(&dir), false).files;
+        assert_eq!(names(&files, &root), vec!["a.PNG", "b.png"]);
+
+        let files = expand_paths(std::slice::from_ref(&dir), true).files;
+        assert_eq!(names(&files, &root), vec!["a.PNG", "b.png", "nested/c.png"]);
+
+        let pattern = format!("{}/*.png", dir);
+        let missing = format!("{}/missing.png", dir);
+        let files = expand_paths(&[pattern.clone(), missing, pattern], false).files;
+        assert_eq!(names(&files, &root), vec!["b.png", "missing.png"]);
+
+        let bracketed = root.join("shot[1].png");
+        fs::write(&bracketed, b"").unwrap();
+        let bracketed = bracketed.to_str().unwrap().to_string();
+        assert!(!is_batch(&bracketed, &BatchArgs::default()));
+        let files = expand_paths(std::slice::from_ref(&bracketed), false).files;
+        assert_eq!(names(&files, &root), vec!["shot[1].png"]);
+
+        // the same file by two different paths is only run once
+        let dotted = format!("{}/./nested/../b.png", dir);
+        let files = expand_paths(&[format!("{}/b.png", dir), dotted], false).files;
+        assert_eq!(names(&files, &root), vec!["b.png"]);
+    }
+
+    #[test]
+    fn test_expansion_failures() {
+        let root = temp_tree("pnglee-batch-failures");
+        let dir = root.to_str().unwrap().to_string();
+        let no_match = format!("{}/*.gif", dir);
+
+        let expansion = expand_paths(&[no_match.clone(), dir], false);
+        assert_eq!(expansion.files.len(), 2);
+        assert_eq!(expansion.failed.len(), 1);
+        let (path, error) = &expansion.failed[0];
+        assert_eq!(path, &no_match);
+        assert_eq!(error_code(error), ErrorCode::NotFound);
+    }
+
+    #[test]
+    fn test_summary() {
+        let ok = Rendered::new(&BatchSummary::new(&[])).unwrap();
+        let results = vec![
+            ("a.png".to_string(), Ok(ok)),
+            (
+                "b.png".to_string(),
+                Err(anyhow!("Bad")).with_code(ErrorCode::InvalidPng),
+            ),
+        ];
+        let summary = BatchSummary::new(&results);
+        assert_eq!(summary.succeeded, 1);
+        assert_eq!(summary.failed[0].file, "b.png");
+        assert_eq!(error_code(&summary.error().unwrap()), ErrorCode::InvalidPng);
+        assert!(BatchSummary::new(&results[..1]).error().is_none());
+    }
+
+    #[test]
+    fn test_decryption_runs_one_file_at_a_time() {
+        let decode = Args::parse_from(["pnglee", "decode", "dir", "ruSt", "--passphrase", "p"]);
+        assert!(decode.command.derives_keys());
+        let plain = Args::parse_from(["pnglee", "decode", "dir", "ruSt"]);
+        assert!(!plain.command.derives_keys());
+    }
+
+    #[test]
+    fn test_run_carries_on_past_failures() {
+        let root = temp_tree("pnglee-batch-run");
+        fs::copy("capy.png", root.join("capy.png")).unwrap();
+        let no_match = format!("{}/*.gif", root.to_str().unwrap());
+        let args = Args::parse_from(["pnglee", "list", root.to_str().unwrap(), "-i", &no_match]);
+        let command = args.command;
+        let (file_path, batch) = command.input().unwrap();
+        let batch = BatchArgs {
+            jobs: Some(2),
+            ..batch.clone()
+        };
+
+        let paths = vec![file_path.to_string(), no_match.clone()];
+        let results = run_paths(&command, &paths, &batch).unwrap();
+        let summary = BatchSummary::new(&results);
+        assert_eq!(summary.files, 4);
+        assert_eq!(summary.succeeded, 1);
+        assert_eq!(summary.failed[0].file, no_match);
+        assert_eq!(summary.failed[0].code, ErrorCode::NotFound);
+        assert!(results[3].0.ends_with("capy.png"));
+        assert!(results[3].1.is_ok());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::chunk_type::ChunkType;
+use crate::known_chunk::{KnownChunk, KnownType};
 use core::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
@@ -6,9 +7,10 @@ use std::fmt::Formatter;
 use anyhow::anyhow;
 use anyhow::Result;
 
-#[derive(Debug)]
+// length is always derived from data and the stored CRC always matches
+// the data unless the chunk was parsed leniently from a damaged file
+#[derive(Debug, Clone)]
 pub struct Chunk {
-    length: u32,
     chunk_type: ChunkType,
     data: Vec<u8>,
     crc: u32,
@@ -17,56 +19,131 @@ pub struct Chunk {
 impl Chunk {
     const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 
-    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let length = data.len() as u32;
-        let combined: Vec<u8> = chunk_type
-            .bytes()
-            .iter()
-            .chain(data.iter())
-            .copied()
-            .collect();
+    // spec limits chunk data to 2^31 - 1 bytes
+    pub const MAX_DATA_LENGTH: usize = (1 << 31) - 1;
 
-        let checksum = Chunk::CRC.checksum(&combined);
+    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Result<Chunk> {
+        Chunk::check_data_length(data.len())?;
+        let checksum = Chunk::checksum(&chunk_type, &data);
 
-        Chunk {
-            length,
+        Ok(Chunk {
             chunk_type,
             data,
             crc: checksum,
+        })
+    }
+
+    fn check_data_length(length: usize) -> Result<()> {
+        if length > Chunk::MAX_DATA_LENGTH {
+            return Err(anyhow!(
+                "Chunk data of {} bytes exceeds the maximum of {}",
+                length,
+                Chunk::MAX_DATA_LENGTH
+            ));
         }
+        Ok(())
+    }
+
+    fn checksum(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+        let mut digest = Chunk::CRC.digest();
+        digest.update(&chunk_type.bytes());
+        digest.update(data);
+        digest.finalize()
+    }
+
+    pub fn set_data(&mut self, data: Vec<u8>) -> Result<()> {
+        Chunk::check_data_length(data.len())?;
+        self.data = data;
+        self.refresh();
+        Ok(())
+    }
+
+    // data can only be set through new and set_data, which check its
+    // length, so only the CRC needs recomputing
+    pub(crate) fn refresh(&mut self) {
+        self.crc = self.computed_crc();
     }
 
+    // the CRC that is written out by as_bytes
     pub fn crc(&self) -> u32 {
-        let type_bytes = self.chunk_type.bytes();
-        let combined: Vec<u8> = type_bytes.iter().chain(self.data.iter()).copied().collect();
+        self.stored_crc()
+    }
+
+    // the CRC read from the file, or computed on construction
+    pub fn stored_crc(&self) -> u32 {
+        self.crc
+    }
 
-        Chunk::CRC.checksum(&combined)
+    pub fn computed_crc(&self) -> u32 {
+        Chunk::checksum(&self.chunk_type, &self.data)
+    }
+
+    pub fn is_crc_valid(&self) -> bool {
+        self.stored_crc() == self.computed_crc()
     }
 
     pub fn length(&self) -> u32 {
-        self.length
+        self.data.len() as u32
     }
 
     pub fn chunk_type(&self) -> &ChunkType {
         &self.chunk_type
     }
 
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn parse_known(&self) -> Result<KnownChunk> {
+        KnownChunk::from_chunk(self)
+    }
+
+    pub fn known_type(&self) -> Option<KnownType> {
+        KnownType::of(&self.chunk_type)
+    }
+
     pub fn data_as_string(&self) -> Result<String> {
-        if self.data.len() == 0 {
+        if self.data.is_empty() {
             return Err(anyhow!("No data to convert to String"));
         }
         let res = String::from_utf8(self.data.clone());
-        
+
         Ok(res?)
     }
 
+    // printable text up to max_chars, otherwise hex of the first bytes, so
+    // it is always safe to show in a terminal
+    pub fn preview(&self, max_chars: usize) -> String {
+        let printable = std::str::from_utf8(&self.data).ok().filter(|text| {
+            text.chars()
+                .all(|c| !c.is_control() || c == '\n' || c == '\t')
+        });
+        let (mut preview, truncated) = match printable {
+            Some(text) => (
+                text.chars()
+                    .take(max_chars)
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect::<String>(),
+                text.chars().count() > max_chars,
+            ),
+            None => {
+                let shown = (max_chars / 2).min(self.data.len());
+                (hex::encode(&self.data[..shown]), shown < self.data.len())
+            }
+        };
+        if truncated {
+            preview.push_str("...");
+        }
+        preview
+    }
+
     pub fn chunk_data_length(bytes: &[u8]) -> u32 {
         let length_bytes = &bytes[..4];
         u32::from_be_bytes(length_bytes.try_into().unwrap())
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.length
+        self.length()
             .to_be_bytes()
             .iter()
             .chain(self.chunk_type.bytes().iter())
@@ -77,15 +154,20 @@ impl Chunk {
     }
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = anyhow::Error;
+impl Chunk {
+    // keeps the CRC stored in the bytes even when it does not match the
+    // data, so damaged files can still be inspected and repaired
+    pub fn try_from_lenient(bytes: &[u8]) -> Result<Chunk> {
+        Chunk::parse(bytes, false)
+    }
 
-    fn try_from(bytes: &[u8]) -> Result<Self> {
+    fn parse(bytes: &[u8], verify_crc: bool) -> Result<Chunk> {
         if bytes.len() < 12 {
             return Err(anyhow!("PNG spec requires at least 12 bytes"));
         }
 
         let length = Chunk::chunk_data_length(bytes);
+        Chunk::check_data_length(length as usize)?;
 
         let chunk_type_bytes = &bytes[4..8];
         let chunk_type_arr: [u8; 4] = chunk_type_bytes.try_into().unwrap();
@@ -104,22 +186,32 @@ impl TryFrom<&[u8]> for Chunk {
         let last_four_bytes: [u8; 4] = bytes[bytes.len() - 4..].try_into().unwrap();
         let check_sum_value = u32::from_be_bytes(last_four_bytes);
 
-        if checksum != check_sum_value {
+        if verify_crc && checksum != check_sum_value {
             return Err(anyhow!("Checksum invalid"));
         }
 
         Ok(Chunk {
-            length,
             chunk_type,
             data,
-            crc: checksum,
+            crc: check_sum_value,
         })
     }
 }
 
+impl TryFrom<&[u8]> for Chunk {
+    type Error = anyhow::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Chunk::parse(bytes, true)
+    }
+}
+
 impl Display for Chunk {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self.data_as_string().unwrap())
+        match std::str::from_utf8(&self.data) {
+            Ok(text) => write!(f, "{}", text),
+            Err(_) => write!(f, "{} bytes of binary data", self.data.len()),
+        }
     }
 }
 
@@ -153,7 +245,7 @@ mod tests {
         let data = "This is where your secret message will be!"
             .as_bytes()
             .to_vec();
-        let chunk = Chunk::new(chunk_type, data);
+        let chunk = Chunk::new(chunk_type, data).unwrap();
         assert_eq!(chunk.length(), 42);
         assert_eq!(chunk.crc(), 2882656334);
     }
@@ -232,6 +324,55 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_oversized_length_rejected() {
+        let mut chunk_data: Vec<u8> = (1u32 << 31).to_be_bytes().to_vec();
+        chunk_data.extend_from_slice(b"RuSt");
+        chunk_data.extend_from_slice(&[0, 0, 0, 0]);
+
+        assert!(Chunk::try_from(chunk_data.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_set_data_refreshes_crc() {
+        let mut chunk = testing_chunk();
+        chunk.set_data(b"New message".to_vec()).unwrap();
+
+        let expected = Chunk::new(
+            ChunkType::from_str("RuSt").unwrap(),
+            b"New message".to_vec(),
+        )
+        .unwrap();
+        assert_eq!(chunk.length(), 11);
+        assert_eq!(chunk.stored_crc(), expected.stored_crc());
+        assert!(chunk.is_crc_valid());
+
+        let reparsed = Chunk::try_from(chunk.as_bytes().as_ref()).unwrap();
+        assert_eq!(reparsed.data(), b"New message");
+    }
+
+    #[test]
+    fn test_lenient_keeps_stored_crc() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        let last = chunk_data.len() - 1;
+        chunk_data[last] ^= 1;
+
+        assert!(Chunk::try_from(chunk_data.as_ref()).is_err());
+
+        let chunk = Chunk::try_from_lenient(chunk_data.as_ref()).unwrap();
+        assert_eq!(chunk.stored_crc(), 2882656334 ^ 1);
+        assert_eq!(chunk.computed_crc(), 2882656334);
+        assert!(!chunk.is_crc_valid());
+        assert_eq!(chunk.as_bytes(), chunk_data);
+    }
+
+    #[test]
+    fn test_stored_and_computed_crc() {
+        let chunk = testing_chunk();
+        assert_eq!(chunk.stored_crc(), 2882656334);
+        assert_eq!(chunk.computed_crc(), 2882656334);
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -252,4 +393,29 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_binary_chunk_display() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let binary = Chunk::new(chunk_type.clone(), vec![0xff, 0x00, 0x9c]).unwrap();
+        assert_eq!(binary.to_string(), "3 bytes of binary data");
+
+        let empty = Chunk::new(chunk_type, vec![]).unwrap();
+        assert_eq!(empty.to_string(), "");
+    }
+
+    #[test]
+    fn test_preview() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let text = Chunk::new(chunk_type.clone(), b"hello\nworld".to_vec()).unwrap();
+        assert_eq!(text.preview(20), "hello world");
+        assert_eq!(text.preview(5), "hello...");
+
+        let binary = Chunk::new(chunk_type.clone(), vec![0, 1, 2, 0xff, 0xfe]).unwrap();
+        assert_eq!(binary.preview(20), "000102fffe");
+        assert_eq!(binary.preview(4), "0001...");
+
+        let escape = Chunk::new(chunk_type, b"\x1b[2Jgotcha".to_vec()).unwrap();
+        assert_eq!(escape.preview(4), "1b5b...");
+    }
 }
@@ -7,7 +7,7 @@ use std::{
 use anyhow::anyhow;
 use anyhow::Result;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChunkType {
     b: [u8; 4],
 }
@@ -18,9 +18,7 @@ impl ChunkType {
     }
 
     fn is_bytes_valid(bytes: [u8; 4]) -> bool {
-        bytes
-            .into_iter()
-            .all(|b| b.is_ascii_alphabetic())
+        bytes.into_iter().all(|b| b.is_ascii_alphabetic())
     }
 
     pub fn is_byte_uppercase_char(&self, byte_position: usize) -> bool {
@@ -31,13 +29,13 @@ impl ChunkType {
         byte.is_ascii_uppercase()
     }
 
-    // critical if first byte is uppercase 
+    // critical if first byte is uppercase
     // e.g. RuSt
     pub fn is_critical(&self) -> bool {
         self.is_byte_uppercase_char(0)
     }
 
-    // public if second byte is uppercase 
+    // public if second byte is uppercase
     // e.g. rUSt
     pub fn is_public(&self) -> bool {
         self.is_byte_uppercase_char(1)
@@ -86,7 +84,7 @@ impl FromStr for ChunkType {
         let bytes: [u8; 4] = s
             .as_bytes()
             .try_into()
-            .expect("Length of string should be 4");
+            .map_err(|_| anyhow!("Chunk type must be 4 bytes, got {}", s.len()))?;
 
         if !ChunkType::is_bytes_valid(bytes) {
             return Err(anyhow!("Provided byte array not valid"));
@@ -0,0 +1,258 @@
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::known_chunk::KnownChunk;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+// implemented for private chunk types so they can be parsed, validated
+// and printed alongside the built-in chunks
+pub trait ChunkCodec: Send + Sync {
+    type Value: Debug + Display;
+
+    fn chunk_type(&self) -> &str;
+
+    fn decode(&self, data: &[u8]) -> Result<Self::Value>;
+
+    fn encode(&self, value: &Self::Value) -> Vec<u8>;
+}
+
+// object safe view of a codec so codecs with different value types
+// can share a registry
+trait ErasedCodec: Send + Sync {
+    fn chunk_type(&self) -> &str;
+
+    fn validate(&self, data: &[u8]) -> Result<()>;
+
+    fn describe(&self, data: &[u8]) -> Result<String>;
+}
+
+impl<C: ChunkCodec> ErasedCodec for C {
+    fn chunk_type(&self) -> &str {
+        ChunkCodec::chunk_type(self)
+    }
+
+    // a chunk is only valid if decoding then encoding gives back
+    // the exact bytes, otherwise rewriting the file would change it
+    fn validate(&self, data: &[u8]) -> Result<()> {
+        let value = self.decode(data)?;
+        if self.encode(&value) != data {
+            return Err(anyhow!(
+                "{} chunk does not round-trip through its codec",
+                ChunkCodec::chunk_type(self)
+            ));
+        }
+        Ok(())
+    }
+
+    fn describe(&self, data: &[u8]) -> Result<String> {
+        Ok(self.decode(data)?.to_string())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CodecRegistry {
+    codecs: Vec<Arc<dyn ErasedCodec>>,
+}
+
+impl CodecRegistry {
+    pub fn register<C: ChunkCodec + 'static>(&mut self, codec: C) -> Result<()> {
+        let chunk_type = ChunkType::from_str(ChunkCodec::chunk_type(&codec))?;
+        if !chunk_type.is_valid() {
+            return Err(anyhow!("Codec chunk type {} not valid", chunk_type));
+        }
+        if KnownChunk::is_known(&chunk_type.to_string()) {
+            return Err(anyhow!("{} is a built-in chunk type", chunk_type));
+        }
+        if self.is_registered(&chunk_type.to_string()) {
+            return Err(anyhow!("A codec for {} is already registered", chunk_type));
+        }
+
+        self.codecs.push(Arc::new(codec));
+        Ok(())
+    }
+
+    pub fn is_registered(&self, chunk_type: &str) -> bool {
+        self.codec_for(chunk_type).is_some()
+    }
+
+    fn codec_for(&self, chunk_type: &str) -> Option<&Arc<dyn ErasedCodec>> {
+        self.codecs.iter().find(|c| c.chunk_type() == chunk_type)
+    }
+
+    // chunks without a registered codec are always valid here
+    pub fn validate(&self, chunk: &Chunk) -> Result<()> {
+        match self.codec_for(&chunk.chunk_type().to_string()) {
+            Some(codec) => codec.validate(chunk.data()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn describe(&self, chunk: &Chunk) -> Option<Result<String>> {
+        self.codec_for(&chunk.chunk_type().to_string())
+            .map(|codec| codec.describe(chunk.data()))
+    }
+
+    pub fn chunk_types(&self) -> Vec<String> {
+        self.codecs
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+}
+
+pub fn encode_chunk<C: ChunkCodec>(codec: &C, value: &C::Value) -> Result<Chunk> {
+    let chunk_type = ChunkType::from_str(codec.chunk_type())?;
+    Chunk::new(chunk_type, codec.encode(value))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use core::fmt;
+
+    #[derive(Debug, PartialEq)]
+    pub struct BoundingBox {
+        pub x: u16,
+        pub y: u16,
+        pub width: u16,
+        pub height: u16,
+    }
+
+    impl Display for BoundingBox {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "{}x{} at ({}, {})",
+                self.width, self.height, self.x, self.y
+            )
+        }
+    }
+
+    pub struct BoundingBoxCodec;
+
+    impl ChunkCodec for BoundingBoxCodec {
+        type Value = BoundingBox;
+
+        fn chunk_type(&self) -> &str {
+            "tmBx"
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<BoundingBox> {
+            if data.len() != 8 {
+                return Err(anyhow!("tmBx chunk must be 8 bytes"));
+            }
+            let field = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+            Ok(BoundingBox {
+                x: field(0),
+                y: field(2),
+                width: field(4),
+                height: field(6),
+            })
+        }
+
+        fn encode(&self, value: &BoundingBox) -> Vec<u8> {
+            [value.x, value.y, value.width, value.height]
+                .iter()
+                .flat_map(|v| v.to_be_bytes())
+                .collect()
+        }
+    }
+
+    struct LossyCodec;
+
+    impl ChunkCodec for LossyCodec {
+        type Value = u8;
+
+        fn chunk_type(&self) -> &str {
+            "prVn"
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<u8> {
+            data.first()
+                .copied()
+                .ok_or_else(|| anyhow!("prVn chunk is empty"))
+        }
+
+        fn encode(&self, value: &u8) -> Vec<u8> {
+            vec![*value]
+        }
+    }
+
+    fn bounding_box() -> BoundingBox {
+        BoundingBox {
+            x: 1,
+            y: 2,
+            width: 30,
+            height: 40,
+        }
+    }
+
+    #[test]
+    fn test_register_codec() {
+        let mut registry = CodecRegistry::default();
+        registry.register(BoundingBoxCodec).unwrap();
+
+        assert!(registry.is_registered("tmBx"));
+        assert!(!registry.is_registered("prVn"));
+        assert!(registry.register(BoundingBoxCodec).is_err());
+    }
+
+    #[test]
+    fn test_register_builtin_type() {
+        struct TextCodec;
+        impl ChunkCodec for TextCodec {
+            type Value = String;
+            fn chunk_type(&self) -> &str {
+                "tEXt"
+            }
+            fn decode(&self, data: &[u8]) -> Result<String> {
+                Ok(String::from_utf8(data.to_vec())?)
+            }
+            fn encode(&self, value: &String) -> Vec<u8> {
+                value.as_bytes().to_vec()
+            }
+        }
+
+        let mut registry = CodecRegistry::default();
+        assert!(registry.register(TextCodec).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = encode_chunk(&BoundingBoxCodec, &bounding_box()).unwrap();
+        assert_eq!(
+            BoundingBoxCodec.decode(chunk.data()).unwrap(),
+            bounding_box()
+        );
+
+        let mut registry = CodecRegistry::default();
+        registry.register(BoundingBoxCodec).unwrap();
+        assert!(registry.validate(&chunk).is_ok());
+        assert_eq!(
+            registry.describe(&chunk).unwrap().unwrap(),
+            "30x40 at (1, 2)"
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_data() {
+        let mut registry = CodecRegistry::default();
+        registry.register(BoundingBoxCodec).unwrap();
+        registry.register(LossyCodec).unwrap();
+
+        let short = Chunk::new(ChunkType::from_str("tmBx").unwrap(), vec![1, 2, 3]).unwrap();
+        assert!(registry.validate(&short).is_err());
+
+        let trailing = Chunk::new(ChunkType::from_str("prVn").unwrap(), vec![1, 2]).unwrap();
+        assert!(registry.validate(&trailing).is_err());
+
+        let unregistered = Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![1]).unwrap();
+        assert!(registry.validate(&unregistered).is_ok());
+        assert!(registry.describe(&unregistered).is_none());
+    }
+}
@@ -1,145 +1,815 @@
-use crate::args::Args;
+use crate::args::{
+    BatchArgs, CapacityArgs, CopyMetaArgs, DecodeArgs, DecryptArgs, DiffArgs, DumpArgs, EncodeArgs,
+    ExtractArgs, HideArgs, InjectArgs, KeygenArgs, ListArgs, PayloadArgs, PixelArgs, PrintArgs,
+    RemoveArgs, RepairArgs, RevealArgs, SelectArgs, SignArgs, StripArgs, VerifyArgs,
+};
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
-use crate::png::Png;
+use crate::codec::CodecRegistry;
+use crate::crypto::{self, KdfParams};
+use crate::diff;
+use crate::output::{ErrorCode, Rendered, WithCode};
+use crate::payload::{self, Payload};
+use crate::pixels::Image;
+use crate::png::{ParseMode, Png, Position};
+use crate::recipients;
+use crate::report::{
+    CapacityReport, ChunkInfo, CopyMetaReport, CoveredChunk, DecodeReport, DiffReport, DumpReport,
+    EncodeReport, ExtractReport, HideReport, InjectReport, KeygenReport, ListReport, ListedChunk,
+    PayloadReport, PayloadSource, PixelComparison, PrintReport, RemoveReport, RepairReport,
+    SignReport, StripReport, VerifyReport, WriteReport,
+};
+use crate::signature::{self, Coverage};
+use crate::stego::{self, LsbOptions};
+use crate::strip::StripRules;
 
-use core::fmt;
 use std::fs;
+use std::io::Write;
+use std::path::Path;
 use std::str::FromStr;
 
 use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
+use clap::Subcommand;
 
-#[derive(Debug, Clone)]
+#[derive(Subcommand, Debug, Clone)]
 pub enum Command {
-    Encode,
-    Decode,
-    Remove,
-    Print,
+    /// Hide a message or file in a new chunk
+    Encode(EncodeArgs),
+    /// Read a message hidden in a chunk
+    Decode(DecodeArgs),
+    /// Remove the first chunk of a type
+    Remove(RemoveArgs),
+    /// Describe the private ancillary chunks
+    Print(PrintArgs),
+    /// List every chunk. Flags are C critical, P public, R reserved bit set, S safe to copy
+    List(ListArgs),
+    /// Show a chunk's data as hex and ASCII
+    Dump(DumpArgs),
+    /// Write a chunk's raw data or whole record to a file or stdout
+    Extract(ExtractArgs),
+    /// Insert a chunk built from a data file, or serialized chunk records
+    Inject(InjectArgs),
+    /// Copy ancillary chunks from one PNG into another
+    CopyMeta(CopyMetaArgs),
+    /// Remove metadata chunks by preset or chunk type
+    Strip(StripArgs),
+    /// Show how two PNG files differ, chunk by chunk
+    Diff(DiffArgs),
+    /// Fix CRC errors and trailing garbage
+    Repair(RepairArgs),
+    /// Generate a key pair for recipients or signing
+    Keygen(KeygenArgs),
+    /// Sign chunks with an Ed25519 key
+    Sign(SignArgs),
+    /// Check a signature and list the chunks it covers
+    Verify(VerifyArgs),
+    /// Hide a message or file in the pixel data
+    Hide(HideArgs),
+    /// Read a message hidden in the pixel data
+    Reveal(RevealArgs),
+    /// Report how many bytes the pixel data can hide
+    Capacity(CapacityArgs),
 }
 
-impl fmt::Display for Command {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Command {
+    pub fn name(&self) -> &'static str {
         match self {
-            Command::Encode => write!(f, "Encode"),
-            Command::Decode => write!(f, "Decode"),
-            Command::Remove => write!(f, "Remove"),
-            Command::Print => write!(f, "Print"),
+            Command::Encode(_) => "encode",
+            Command::Decode(_) => "decode",
+            Command::Remove(_) => "remove",
+            Command::Print(_) => "print",
+            Command::List(_) => "list",
+            Command::Dump(_) => "dump",
+            Command::Extract(_) => "extract",
+            Command::Inject(_) => "inject",
+            Command::CopyMeta(_) => "copy-meta",
+            Command::Strip(_) => "strip",
+            Command::Diff(_) => "diff",
+            Command::Repair(_) => "repair",
+            Command::Keygen(_) => "keygen",
+            Command::Sign(_) => "sign",
+            Command::Verify(_) => "verify",
+            Command::Hide(_) => "hide",
+            Command::Reveal(_) => "reveal",
+            Command::Capacity(_) => "capacity",
         }
     }
-}
 
-impl FromStr for Command {
-    type Err = String;
+    pub fn run(self) -> Result<Rendered> {
+        match self {
+            Command::Encode(args) => Rendered::new(&Command::handle_encode(args)?),
+            Command::Decode(args) => Rendered::new(&Command::handle_decode(args)?),
+            Command::Remove(args) => Rendered::new(&Command::handle_remove(args)?),
+            Command::Print(args) => Rendered::new(&Command::handle_print(args)?),
+            Command::List(args) => Rendered::new(&Command::handle_list(args)?),
+            Command::Dump(args) => Rendered::new(&Command::handle_dump(args)?),
+            Command::Extract(args) => Rendered::new(&Command::handle_extract(args)?),
+            Command::Inject(args) => Rendered::new(&Command::handle_inject(args)?),
+            Command::CopyMeta(args) => Rendered::new(&Command::handle_copy_meta(args)?),
+            Command::Strip(args) => Rendered::new(&Command::handle_strip(args)?),
+            Command::Diff(args) => Rendered::new(&Command::handle_diff(args)?),
+            Command::Repair(args) => Rendered::new(&Command::handle_repair(args)?),
+            Command::Keygen(args) => Rendered::new(&Command::handle_keygen(args)?),
+            Command::Sign(args) => Rendered::new(&Command::handle_sign(args)?),
+            Command::Verify(args) => Rendered::new(&Command::handle_verify(args)?),
+            Command::Hide(args) => Rendered::new(&Command::handle_hide(args)?),
+            Command::Reveal(args) => Rendered::new(&Command::handle_reveal(args)?),
+            Command::Capacity(args) => Rendered::new(&Command::handle_capacity(args)?),
+        }
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "encode" => Ok(Command::Encode),
-            "decode" => Ok(Command::Decode),
-            "remove" => Ok(Command::Remove),
-            "print" => Ok(Command::Print),
-            _ => Err(format!(
-                "Invalid action: '{}'. Use one of: [encode, decode, remove, print].",
-                s
-            )),
+    // the input path and batch options of commands that read PNG files
+    pub fn input(&self) -> Option<(&str, &BatchArgs)> {
+        match self {
+            Command::Encode(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Decode(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Remove(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Print(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::List(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Dump(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Extract(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Inject(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::CopyMeta(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Strip(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Repair(args) => Some((&args.file_path, &args.batch)),
+            Command::Sign(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Verify(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Hide(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Reveal(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Capacity(args) => Some((&args.input.file_path, &args.input.batch)),
+            Command::Diff(_) | Command::Keygen(_) => None,
         }
     }
-}
 
-impl Command {
-    pub fn handle_encode(args: Args) -> Result<()> {
-        let file_path = args
-            .file_path
-            .ok_or_else(|| anyhow::anyhow!("No file path provided"))?;
-        let chunk_type = args
-            .chunk_type
-            .ok_or_else(|| anyhow::anyhow!("No chunk type provided"))?;
-        let message = args
-            .message
-            .ok_or_else(|| anyhow::anyhow!("No message provided"))?;
-
-        println!("Encoding file with message {}", &message);
-        let contents = fs::read(&file_path)?;
-        let parsed_chunk_type = ChunkType::from_str(&chunk_type)?;
-        if !parsed_chunk_type.is_valid() {
-            return Err(anyhow!("Provided chunk type not valid"));
-        }
-
-        let chunk = Chunk::new(parsed_chunk_type, message.as_bytes().to_vec());
-        let mut png = Png::try_from(contents.as_ref())?;
-        png.append_chunk(chunk);
-
-        match &args.output_file {
-            Some(op) => {
-                fs::write(op, png.as_bytes())?;
-            }
-            None => {
-                fs::write(&file_path, png.as_bytes())?;
-            }
+    // the same command, run on another input file
+    pub fn with_input(&self, file_path: &str) -> Command {
+        let mut command = self.clone();
+        let path = match &mut command {
+            Command::Encode(args) => &mut args.input.file_path,
+            Command::Decode(args) => &mut args.input.file_path,
+            Command::Remove(args) => &mut args.input.file_path,
+            Command::Print(args) => &mut args.input.file_path,
+            Command::List(args) => &mut args.input.file_path,
+            Command::Dump(args) => &mut args.input.file_path,
+            Command::Extract(args) => &mut args.input.file_path,
+            Command::Inject(args) => &mut args.input.file_path,
+            Command::CopyMeta(args) => &mut args.input.file_path,
+            Command::Strip(args) => &mut args.input.file_path,
+            Command::Repair(args) => &mut args.file_path,
+            Command::Sign(args) => &mut args.input.file_path,
+            Command::Verify(args) => &mut args.input.file_path,
+            Command::Hide(args) => &mut args.input.file_path,
+            Command::Reveal(args) => &mut args.input.file_path,
+            Command::Capacity(args) => &mut args.input.file_path,
+            Command::Diff(_) | Command::Keygen(_) => return command,
+        };
+        *path = file_path.to_string();
+        command
+    }
+
+    // files named on the command line that every input would write to
+    pub fn fixed_output(&self) -> Option<&str> {
+        let path = match self {
+            Command::Encode(args) => &args.output_file,
+            Command::Decode(args) => &args.decrypt.out,
+            Command::Remove(args) => &args.output_file,
+            Command::Print(args) => &args.dump_trailing,
+            Command::Extract(args) => &args.output_file,
+            Command::Inject(args) => &args.output_file,
+            Command::CopyMeta(args) => &args.output_file,
+            Command::Strip(args) => &args.output_file,
+            Command::Repair(args) => &args.output_file,
+            Command::Sign(args) => &args.output_file,
+            Command::Hide(args) => &args.output_file,
+            Command::Reveal(args) => &args.decrypt.out,
+            _ => return None,
+        };
+        path.as_deref()
+    }
+
+    // decryption derives a key for every file, with costs read from the
+    // file, so batches of these run one file at a time unless asked not to
+    pub fn derives_keys(&self) -> bool {
+        match self {
+            Command::Decode(args) => args.decrypt.passphrase.is_some(),
+            Command::Reveal(args) => args.decrypt.passphrase.is_some(),
+            _ => false,
         }
-        Ok(())
     }
 
-    pub fn handle_decode(args: Args) -> Result<String> {
-        let file_path = args
-            .file_path
-            .ok_or_else(|| anyhow::anyhow!("No file path provided"))?;
-        let chunk_type = args
-            .chunk_type
-            .ok_or_else(|| anyhow::anyhow!("No chunk type provided"))?;
+    // warnings go to stderr so they never mix with structured output
+    fn read_png(file_path: &str, lenient: bool) -> Result<Png> {
+        let contents =
+            fs::read(file_path).with_context(|| format!("Could not read {}", file_path))?;
+        let mode = if lenient {
+            ParseMode::Lenient
+        } else {
+            ParseMode::Strict
+        };
 
-        let contents = fs::read(&file_path)?;
-        let png = Png::try_from(contents.as_ref())?;
-        let secret_chunk = png.chunk_by_type(&chunk_type);
+        let png = Png::parse(&contents, mode).with_code(ErrorCode::InvalidPng)?;
+        for diagnostic in png.diagnostics() {
+            eprintln!("Warning: {}: {}", file_path, diagnostic);
+        }
+        Ok(png)
+    }
 
-        match secret_chunk {
-            Some(c) => {
-                let secret_message = c.data_as_string()?;
-                println!("Super secret message: {}", secret_message);
-                return Ok(secret_message);
-            }
+    pub fn handle_encode(args: EncodeArgs) -> Result<EncodeReport> {
+        let (payload, source) = Command::payload_from_args(args.message.as_deref(), &args.payload)?;
+        let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+
+        let payload_bytes = Command::encode_payload(&payload, &args.payload)?;
+        let stored_bytes = payload_bytes.len();
+        let chunk_datas = match args.chunk_size {
+            Some(size) => payload::split_into_parts(&payload_bytes, size as usize)?,
+            None => vec![payload_bytes],
+        };
+        let chunks = chunk_datas.len();
+
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.set_keep_unsafe(args.write.keep_unsafe);
+        for data in chunk_datas {
+            png.append_chunk(Chunk::new(chunk_type.clone(), data)?);
+        }
+
+        let output_path = args.output_file.as_ref().unwrap_or(&args.input.file_path);
+        Ok(EncodeReport {
+            chunk_type: args.chunk_type.clone(),
+            source,
+            stored_bytes,
+            chunks,
+            written: Command::write_png(&mut png, output_path, args.write.strip_trailing)?,
+        })
+    }
+
+    // hides the payload in the low bits of the pixel data
+    pub fn handle_hide(args: HideArgs) -> Result<HideReport> {
+        let (payload, source) = Command::payload_from_args(args.message.as_deref(), &args.payload)?;
+        let payload_bytes = Command::encode_payload(&payload, &args.payload)?;
+
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.set_keep_unsafe(args.write.keep_unsafe);
+        let options = Command::lsb_options(&args.pixels)?;
+        let mut image = Image::from_png(&png).with_code(ErrorCode::InvalidPng)?;
+        let capacity = stego::capacity(&image, &options).with_code(ErrorCode::Usage)?;
+
+        stego::embed(&mut image, &payload_bytes, &options).with_code(ErrorCode::Capacity)?;
+        image.write_to(&mut png)?;
+
+        let output_path = args.output_file.as_ref().unwrap_or(&args.input.file_path);
+        Ok(HideReport {
+            source,
+            stored_bytes: payload_bytes.len(),
+            capacity,
+            written: Command::write_png(&mut png, output_path, args.write.strip_trailing)?,
+        })
+    }
+
+    fn lsb_options(args: &PixelArgs) -> Result<LsbOptions> {
+        Ok(LsbOptions {
+            bits_per_channel: args.bits,
+            channels: match &args.channels {
+                Some(letters) => stego::parse_channels(letters).with_code(ErrorCode::Usage)?,
+                None => vec![],
+            },
+            order_key: args.lsb_key.clone(),
+        })
+    }
+
+    // the payload comes from --file if given, otherwise the message
+    fn payload_from_args(
+        message: Option<&str>,
+        args: &PayloadArgs,
+    ) -> Result<(Payload, PayloadSource)> {
+        let payload_path = match &args.file {
+            Some(path) => path,
             None => {
-                println!("No chunk found for type {}", &chunk_type);
-                return Ok(String::from(""));
+                let message = message
+                    .ok_or_else(|| anyhow!("No message provided"))
+                    .with_code(ErrorCode::Usage)?;
+                let payload = Payload::new(message.as_bytes().to_vec());
+                return Ok((
+                    Command::compress_if_asked(payload, args.compress),
+                    PayloadSource::Message {
+                        length: message.len(),
+                    },
+                ));
             }
+        };
+
+        let mut payload = Payload::new(fs::read(payload_path)?);
+        let source = PayloadSource::File {
+            path: payload_path.clone(),
+            length: payload.data.len(),
+        };
+        if !args.no_name {
+            let name = Path::new(payload_path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| anyhow!("Could not get file name from {}", payload_path))?;
+            payload = payload.with_name(name);
+        }
+        if let Some(mime) = &args.mime {
+            payload = payload.with_mime(mime);
+        }
+        Ok((Command::compress_if_asked(payload, args.compress), source))
+    }
+
+    fn compress_if_asked(payload: Payload, compress: bool) -> Payload {
+        if compress {
+            payload.with_compression()
+        } else {
+            payload
+        }
+    }
+
+    // the hidden bytes are the payload, encrypted if a passphrase is given
+    // recipients are paths to public key files
+    fn encode_payload(payload: &Payload, args: &PayloadArgs) -> Result<Vec<u8>> {
+        let bytes = payload.to_bytes()?;
+        if let Some(passphrase) = &args.passphrase {
+            return crypto::encrypt_with_passphrase(&bytes, passphrase, KdfParams::default());
+        }
+        if args.recipient.is_empty() {
+            return Ok(bytes);
+        }
+
+        let public_keys = args
+            .recipient
+            .iter()
+            .map(|path| {
+                recipients::parse_public_key(&fs::read_to_string(path)?)
+                    .with_code(ErrorCode::InvalidKey)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        recipients::seal_for_recipients(&bytes, &public_keys)
+    }
+
+    // identity is the path to a private key file
+    fn decode_payload(bytes: &[u8], args: &DecryptArgs) -> Result<Payload> {
+        if crypto::is_passphrase_encrypted(bytes) {
+            let passphrase = args
+                .passphrase
+                .as_deref()
+                .ok_or_else(|| anyhow!("Payload is encrypted, use --passphrase to decrypt it"))
+                .with_code(ErrorCode::Usage)?;
+            let decrypted = crypto::decrypt_with_passphrase(bytes, passphrase)
+                .with_code(ErrorCode::Decryption)?;
+            return Payload::from_bytes(&decrypted).with_code(ErrorCode::InvalidPayload);
+        }
+
+        if recipients::is_sealed(bytes) {
+            let identity = args
+                .identity
+                .as_deref()
+                .ok_or_else(|| {
+                    anyhow!("Payload is sealed to recipients, use --identity to decrypt it")
+                })
+                .with_code(ErrorCode::Usage)?;
+            let secret = recipients::parse_secret_key(&fs::read_to_string(identity)?)
+                .with_code(ErrorCode::InvalidKey)?;
+            let opened =
+                recipients::open_with_secret(bytes, &secret).with_code(ErrorCode::Decryption)?;
+            return Payload::from_bytes(&opened).with_code(ErrorCode::InvalidPayload);
+        }
+
+        Payload::from_bytes(bytes).with_code(ErrorCode::InvalidPayload)
+    }
+
+    pub fn handle_decode(args: DecodeArgs) -> Result<DecodeReport> {
+        let chunk_type = args.chunk_type;
+
+        let png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        let secret_chunks: Vec<&[u8]> = png
+            .chunks_by_type(&chunk_type)
+            .iter()
+            .map(|c| c.data())
+            .collect();
+
+        // a lone unsplit chunk keeps the old first-match behaviour
+        let (secret_data, used_chunks) = match secret_chunks.first() {
+            Some(first) if !payload::is_part(first) => (Some(first.to_vec()), 1),
+            Some(_) => (
+                Some(payload::join_parts(&secret_chunks).with_code(ErrorCode::InvalidPayload)?),
+                secret_chunks.len(),
+            ),
+            None => (None, 0),
+        };
+        let chunks: Vec<ChunkInfo> = ChunkInfo::from_png(&png)
+            .into_iter()
+            .filter(|info| info.chunk_type == chunk_type)
+            .take(used_chunks)
+            .collect();
+
+        let data = secret_data
+            .ok_or_else(|| anyhow!("No chunk found for type {}", chunk_type))
+            .with_code(ErrorCode::NotFound)?;
+        let payload = Command::decode_payload(&data, &args.decrypt)?;
+        Ok(DecodeReport {
+            payload: Command::output_payload(payload, args.decrypt.out.as_deref())?,
+            chunk_type,
+            chunks,
+        })
+    }
+
+    pub fn handle_reveal(args: RevealArgs) -> Result<PayloadReport> {
+        let png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        let options = Command::lsb_options(&args.pixels)?;
+        let image = Image::from_png(&png).with_code(ErrorCode::InvalidPng)?;
+        let data = stego::extract(&image, &options).with_code(ErrorCode::NotFound)?;
+        let payload = Command::decode_payload(&data, &args.decrypt)?;
+        Command::output_payload(payload, args.decrypt.out.as_deref())
+    }
+
+    // text payloads are returned, anything else needs an output file
+    fn output_payload(payload: Payload, out: Option<&str>) -> Result<PayloadReport> {
+        if let Some(out) = out {
+            fs::write(out, &payload.data)?;
         }
+
+        let length = payload.data.len();
+        let message = String::from_utf8(payload.data).ok();
+        if out.is_none() && message.is_none() {
+            return Err(anyhow!(
+                "Payload is binary, use --out to write it to a file"
+            ))
+            .with_code(ErrorCode::Usage);
+        }
+        Ok(PayloadReport {
+            name: payload.name,
+            mime: payload.mime,
+            length,
+            message,
+            written_to: out.map(String::from),
+        })
+    }
+
+    pub fn handle_capacity(args: CapacityArgs) -> Result<CapacityReport> {
+        let png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        let options = Command::lsb_options(&args.pixels)?;
+        let image = Image::from_png(&png).with_code(ErrorCode::InvalidPng)?;
+        Ok(CapacityReport {
+            width: image.header.width,
+            height: image.header.height,
+            bits_per_channel: options.bits_per_channel,
+            capacity: stego::capacity(&image, &options).with_code(ErrorCode::Usage)?,
+        })
+    }
+
+    pub fn handle_remove(args: RemoveArgs) -> Result<RemoveReport> {
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.set_keep_unsafe(args.write.keep_unsafe);
+        let infos = ChunkInfo::from_png(&png);
+        png.remove_first_chunk(&args.chunk_type)
+            .with_code(ErrorCode::NotFound)?;
+        // the removal succeeded, so the chunk was listed
+        let removed = infos
+            .into_iter()
+            .find(|info| info.chunk_type == args.chunk_type)
+            .unwrap();
+
+        let output_path = args.output_file.as_ref().unwrap_or(&args.input.file_path);
+        Ok(RemoveReport {
+            removed,
+            written: Command::write_png(&mut png, output_path, args.write.strip_trailing)?,
+        })
+    }
+
+    // always parses leniently, as only damaged files need repairing
+    pub fn handle_repair(args: RepairArgs) -> Result<RepairReport> {
+        let file_path = args.file_path;
+
+        let contents =
+            fs::read(&file_path).with_context(|| format!("Could not read {}", file_path))?;
+        let mut png = Png::parse(&contents, ParseMode::Lenient).with_code(ErrorCode::InvalidPng)?;
+        let fixes: Vec<String> = png.repair()?.iter().map(|d| d.to_string()).collect();
+
+        let output_path = args.output_file.as_ref().unwrap_or(&file_path);
+        let written = Command::write_png(&mut png, output_path, !args.keep_trailing)?;
+        Ok(RepairReport {
+            file_path,
+            fixes,
+            written,
+        })
+    }
+
+    // data after IEND is kept unless asked to strip it
+    fn write_png(png: &mut Png, path: &str, strip_trailing: bool) -> Result<WriteReport> {
+        let stripped = if strip_trailing {
+            png.strip_trailing_data().len()
+        } else {
+            0
+        };
+        fs::write(path, png.as_bytes())?;
+        Ok(WriteReport {
+            output: path.to_string(),
+            discarded: png
+                .discarded_chunks()
+                .iter()
+                .map(|c| c.chunk_type().to_string())
+                .collect(),
+            stripped_trailing_bytes: stripped,
+        })
+    }
+
+    // writes <path>.key and <path>.pub
+    pub fn handle_keygen(args: KeygenArgs) -> Result<KeygenReport> {
+        let secret_path = format!("{}.key", args.key_path);
+        let public_path = format!("{}.pub", args.key_path);
+        if Path::new(&secret_path).exists() {
+            return Err(anyhow!("{} already exists", secret_path)).with_code(ErrorCode::Usage);
+        }
+
+        let key_id = if args.signing {
+            let key = signature::generate_signing_key();
+            Command::write_secret_file(&secret_path, &signature::encode_signing_key(&key))?;
+            fs::write(
+                &public_path,
+                signature::encode_verifying_key(&key.verifying_key()),
+            )?;
+            hex::encode(crypto::key_id(key.verifying_key().as_bytes()))
+        } else {
+            let (secret, public) = recipients::generate_keypair();
+            Command::write_secret_file(&secret_path, &recipients::encode_secret_key(&secret))?;
+            fs::write(&public_path, recipients::encode_public_key(&public))?;
+            hex::encode(crypto::key_id(public.as_bytes()))
+        };
+        Ok(KeygenReport {
+            key_id,
+            signing: args.signing,
+            secret_path,
+            public_path,
+        })
+    }
+
+    // signs in place unless an output file is given
+    pub fn handle_sign(args: SignArgs) -> Result<SignReport> {
+        let file_path = args.input.file_path;
+        let key = signature::parse_signing_key(&fs::read_to_string(&args.key)?)
+            .with_code(ErrorCode::InvalidKey)?;
+        let coverage = if args.cover.is_empty() {
+            Coverage::Critical
+        } else {
+            Coverage::Types(args.cover)
+        };
+
+        let mut png = Command::read_png(&file_path, args.input.lenient)?;
+        png.set_keep_unsafe(args.write.keep_unsafe);
+        let covered = signature::sign_png(&mut png, &key, coverage)?;
+
+        let output_path = args.output_file.as_ref().unwrap_or(&file_path);
+        Ok(SignReport {
+            key_id: hex::encode(crypto::key_id(key.verifying_key().as_bytes())),
+            covered: CoveredChunk::from_pairs(covered),
+            written: Command::write_png(&mut png, output_path, args.write.strip_trailing)?,
+        })
+    }
+
+    pub fn handle_verify(args: VerifyArgs) -> Result<VerifyReport> {
+        let key = signature::parse_verifying_key(&fs::read_to_string(&args.key)?)
+            .with_code(ErrorCode::InvalidKey)?;
+
+        let png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        let verification = signature::verify_png(&png, &key).with_code(ErrorCode::Verification)?;
+        Ok(VerifyReport {
+            key_id: verification.key_id,
+            covered: CoveredChunk::from_pairs(verification.covered),
+        })
     }
 
-    pub fn handle_remove(args: Args) -> Result<()> {
-        let file_path = args
-            .file_path
-            .ok_or_else(|| anyhow::anyhow!("No file path provided"))?;
-        let chunk_type = args
-            .chunk_type
-            .ok_or_else(|| anyhow::anyhow!("No chunk type provided"))?;
-        println!("Removing for chunk type {}", chunk_type);
+    // private keys are only readable by their owner
+    fn write_secret_file(path: &str, contents: &str) -> Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
 
-        let contents = fs::read(&file_path)?;
-        let mut png = Png::try_from(contents.as_ref())?;
-        png.remove_first_chunk(&chunk_type)?;
-        fs::write(&file_path, png.as_bytes())?;
+        let mut file = options.open(path)?;
+        file.write_all(contents.as_bytes())?;
         Ok(())
     }
 
-    pub fn handle_print(args: Args) -> Result<Vec<String>> {
-        let file_path = args
-            .file_path
-            .ok_or_else(|| anyhow::anyhow!("No file path provided"))?;
+    pub fn handle_print(args: PrintArgs) -> Result<PrintReport> {
+        Command::handle_print_with_codecs(args, CodecRegistry::default())
+    }
 
-        let contents = fs::read(&file_path)?;
-        let png = Png::try_from(contents.as_ref())?;
+    // lets binaries built on this crate print their own private chunks
+    pub fn handle_print_with_codecs(args: PrintArgs, codecs: CodecRegistry) -> Result<PrintReport> {
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.use_codecs(codecs)?;
 
-        let chunk_msgs: Vec<String> = png.chunks()
+        let descriptions: Vec<String> = png
+            .chunks()
             .iter()
             .filter(|c| !c.chunk_type().is_critical() && !c.chunk_type().is_public())
-            .map(|c| c.to_string())
-            .collect();
-        
+            .map(|c| png.describe_chunk(c))
+            .collect::<Result<_>>()?;
 
-        for chunk_msg in &chunk_msgs {
-            println!("{}", chunk_msg);
+        let trailing_data = png.trailing_data();
+        if let Some(dump_path) = &args.dump_trailing {
+            fs::write(dump_path, trailing_data)?;
         }
 
-        Ok(chunk_msgs)
+        Ok(PrintReport {
+            chunks: ChunkInfo::from_png(&png),
+            descriptions,
+            trailing_bytes: trailing_data.len(),
+            dumped_to: args.dump_trailing,
+        })
+    }
+
+    pub fn handle_list(args: ListArgs) -> Result<ListReport> {
+        Command::handle_list_with_codecs(args, CodecRegistry::default())
+    }
+
+    pub fn handle_list_with_codecs(args: ListArgs, codecs: CodecRegistry) -> Result<ListReport> {
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.use_codecs(codecs)?;
+        Ok(ListReport {
+            chunks: ListedChunk::from_png(&png),
+            trailing_bytes: png.trailing_data().len(),
+        })
+    }
+
+    fn select_chunk(png: &Png, select: &SelectArgs) -> Result<usize> {
+        let selected = match (&select.chunk_type, select.index) {
+            (_, Some(index)) => (index < png.chunks().len())
+                .then_some(index)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No chunk at index {}, file has {} chunks",
+                        index,
+                        png.chunks().len()
+                    )
+                }),
+            (Some(chunk_type), None) => {
+                let indices = png.indices_of(chunk_type);
+                indices.get(select.occurrence).copied().ok_or_else(|| {
+                    anyhow!(
+                        "No {} chunk number {}, file has {}",
+                        chunk_type,
+                        select.occurrence,
+                        indices.len()
+                    )
+                })
+            }
+            (None, None) => Err(anyhow!("Give a chunk type or --index")),
+        };
+        selected.with_code(ErrorCode::NotFound)
+    }
+
+    pub fn handle_dump(args: DumpArgs) -> Result<DumpReport> {
+        let png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        let index = Command::select_chunk(&png, &args.select)?;
+        Ok(DumpReport {
+            chunk: ChunkInfo::from_png(&png).swap_remove(index),
+            data: png.chunks()[index].data().to_vec(),
+        })
+    }
+
+    // without an output file the bytes are left in the report for stdout
+    pub fn handle_extract(args: ExtractArgs) -> Result<ExtractReport> {
+        let png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        let index = Command::select_chunk(&png, &args.select)?;
+        let chunk = &png.chunks()[index];
+        let bytes = if args.record {
+            chunk.as_bytes()
+        } else {
+            chunk.data().to_vec()
+        };
+
+        if let Some(out) = &args.output_file {
+            fs::write(out, &bytes)?;
+        }
+        Ok(ExtractReport {
+            chunk: ChunkInfo::from_png(&png).swap_remove(index),
+            record: args.record,
+            length: bytes.len(),
+            data: args.output_file.is_none().then_some(bytes),
+            written_to: args.output_file,
+        })
+    }
+
+    pub fn handle_inject(args: InjectArgs) -> Result<InjectReport> {
+        let chunks = match (&args.record_file, &args.chunk_type, &args.data_file) {
+            (Some(path), _, _) => {
+                Png::parse_chunk_records(&fs::read(path)?).with_code(ErrorCode::InvalidPng)?
+            }
+            (None, Some(chunk_type), Some(path)) => {
+                vec![Chunk::new(
+                    ChunkType::from_str(chunk_type)?,
+                    fs::read(path)?,
+                )?]
+            }
+            _ => {
+                return Err(anyhow!("Give --type and --data-file, or --record-file"))
+                    .with_code(ErrorCode::Usage)
+            }
+        };
+
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.set_keep_unsafe(args.write.keep_unsafe);
+        let first = png
+            .position_index(args.position)
+            .with_code(ErrorCode::Usage)?;
+        let count = chunks.len();
+        for (offset, chunk) in chunks.into_iter().enumerate() {
+            png.insert_chunk(Position::Index(first + offset), chunk)
+                .with_code(ErrorCode::Usage)?;
+        }
+
+        let output_path = args.output_file.as_ref().unwrap_or(&args.input.file_path);
+        Ok(InjectReport {
+            injected: ChunkInfo::from_png(&png)
+                .into_iter()
+                .skip(first)
+                .take(count)
+                .collect(),
+            written: Command::write_png(&mut png, output_path, args.write.strip_trailing)?,
+        })
+    }
+
+    // copies into the destination in place unless an output file is given
+    pub fn handle_copy_meta(args: CopyMetaArgs) -> Result<CopyMetaReport> {
+        let source = Command::read_png(&args.source_path, args.input.lenient)?;
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.set_keep_unsafe(args.write.keep_unsafe);
+
+        let copied = if args.types.is_empty() {
+            png.copy_ancillary_from(&source, |c| c.chunk_type().is_safe_to_copy())
+        } else {
+            png.copy_ancillary_from(&source, |c| {
+                args.types.contains(&c.chunk_type().to_string())
+            })
+        };
+
+        let output_path = args.output_file.as_ref().unwrap_or(&args.input.file_path);
+        Ok(CopyMetaReport {
+            source: args.source_path.clone(),
+            copied,
+            written: Command::write_png(&mut png, output_path, args.write.strip_trailing)?,
+        })
+    }
+
+    pub fn handle_strip(args: StripArgs) -> Result<StripReport> {
+        let critical = args
+            .remove
+            .iter()
+            .find(|t| ChunkType::from_str(t).is_ok_and(|chunk_type| chunk_type.is_critical()));
+        if let Some(chunk_type) = critical {
+            return Err(anyhow!("Cannot strip critical chunk {}", chunk_type))
+                .with_code(ErrorCode::Usage);
+        }
+        let rules = StripRules {
+            preset: args.preset,
+            remove: args.remove,
+            keep: args.keep,
+        };
+
+        let mut png = Command::read_png(&args.input.file_path, args.input.lenient)?;
+        png.set_keep_unsafe(args.write.keep_unsafe);
+        let removed: Vec<ChunkInfo> = ChunkInfo::from_png(&png)
+            .into_iter()
+            .zip(png.chunks())
+            .filter(|(_, chunk)| rules.should_strip(chunk))
+            .map(|(info, _)| info)
+            .collect();
+        png.retain_chunks(|chunk| !rules.should_strip(chunk));
+
+        let output_path = args.output_file.as_ref().unwrap_or(&args.input.file_path);
+        Ok(StripReport {
+            bytes_removed: removed.iter().map(|c| c.length as usize + 12).sum(),
+            removed,
+            written: Command::write_png(&mut png, output_path, args.write.strip_trailing)?,
+        })
+    }
+
+    // images that cannot be decoded or differ in format still get a chunk
+    // diff, with the reason the pixels were not compared
+    pub fn handle_diff(args: DiffArgs) -> Result<DiffReport> {
+        let a = Command::read_png(&args.a, args.lenient)?;
+        let b = Command::read_png(&args.b, args.lenient)?;
+
+        let diff = diff::diff_pngs(&a, &b);
+        let pixels = args.pixels.then(|| match diff::diff_pixels(&a, &b) {
+            Ok(pixels) => PixelComparison::Compared(pixels),
+            Err(e) => PixelComparison::Skipped {
+                reason: format!("{:#}", e),
+            },
+        });
+
+        Ok(DiffReport {
+            a: args.a,
+            b: args.b,
+            identical: diff.is_identical(),
+            changes: diff.changes,
+            image_data: diff.image_data,
+            pixels,
+        })
     }
 }
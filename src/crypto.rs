@@ -0,0 +1,274 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+pub const PASSPHRASE_MAGIC: [u8; 4] = *b"pLeE";
+pub const VERSION: u8 = 1;
+pub const KEY_LENGTH: usize = 32;
+pub const KEY_ID_LENGTH: usize = 8;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+// ceilings on the costs read from a payload, so a crafted chunk can't
+// ask for terabytes of memory or hours of hashing. Memory is kept well
+// above the 19 MiB default but low enough for several decryptions to
+// run side by side
+const MAX_MEMORY_KIB: u32 = 256 * 1024;
+const MAX_ITERATIONS: u32 = 16;
+const MAX_PARALLELISM: u32 = 16;
+
+// argon2id cost parameters, stored in the chunk so they can be raised
+// later without breaking older files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> KdfParams {
+        KdfParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    fn to_bytes(self) -> Vec<u8> {
+        [self.memory_kib, self.iterations, self.parallelism]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect()
+    }
+
+    fn check_limits(&self) -> Result<()> {
+        if self.memory_kib > MAX_MEMORY_KIB
+            || self.iterations > MAX_ITERATIONS
+            || self.parallelism > MAX_PARALLELISM
+        {
+            return Err(anyhow!(
+                "Key derivation parameters too costly: {} KiB, {} iterations, {} lanes",
+                self.memory_kib,
+                self.iterations,
+                self.parallelism
+            ));
+        }
+        Ok(())
+    }
+
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; KEY_LENGTH]>> {
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LENGTH),
+        )
+        .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+
+        let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
+
+// short fingerprint of a public key, shown to users and used to match
+// a key to a recipient entry or signature
+pub fn key_id(public_key: &[u8]) -> [u8; KEY_ID_LENGTH] {
+    Sha256::digest(public_key)[..KEY_ID_LENGTH]
+        .try_into()
+        .unwrap()
+}
+
+// key files are a single line of a type prefix and the hex key
+pub fn encode_key_file(prefix: &str, key: &[u8]) -> String {
+    format!("{} {}\n", prefix, hex::encode(key))
+}
+
+pub fn decode_key_file(prefix: &str, contents: &str) -> Result<[u8; 32]> {
+    let (found_prefix, key_hex) = contents
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("Key file is not in pnglee format"))?;
+    if found_prefix != prefix {
+        return Err(anyhow!("Expected a {} key, found {}", prefix, found_prefix));
+    }
+
+    hex::decode(key_hex.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("Key must be 32 bytes"))
+}
+
+pub fn is_passphrase_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PASSPHRASE_MAGIC)
+}
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    use chacha20poly1305::aead::rand_core::RngCore;
+
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// the header is passed as associated data, so changing the salt, KDF
+// parameters or anything else in it fails authentication
+pub fn seal(key: &[u8; KEY_LENGTH], header: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: header,
+            },
+        )
+        .map_err(|_| anyhow!("Encryption failed"))?;
+
+    Ok(header
+        .iter()
+        .chain(nonce.iter())
+        .chain(ciphertext.iter())
+        .copied()
+        .collect())
+}
+
+// expects the bytes written by seal, split after the header
+pub fn open(key: &[u8; KEY_LENGTH], header: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LENGTH {
+        return Err(anyhow!("Encrypted payload is truncated"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| anyhow!("Decryption failed: wrong key or tampered payload"))
+}
+
+// layout: magic, version, KDF parameters, salt, nonce, ciphertext
+pub fn encrypt_with_passphrase(
+    plaintext: &[u8],
+    passphrase: &str,
+    params: KdfParams,
+) -> Result<Vec<u8>> {
+    let salt: [u8; SALT_LENGTH] = random_bytes();
+    let key = params.derive_key(passphrase, &salt)?;
+
+    let mut header = PASSPHRASE_MAGIC.to_vec();
+    header.push(VERSION);
+    header.extend(params.to_bytes());
+    header.extend_from_slice(&salt);
+
+    seal(&key, &header, plaintext)
+}
+
+pub fn decrypt_with_passphrase(bytes: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let header_length = PASSPHRASE_MAGIC.len() + 1 + 12 + SALT_LENGTH;
+    if !is_passphrase_encrypted(bytes) || bytes.len() < header_length {
+        return Err(anyhow!("Payload is not passphrase encrypted"));
+    }
+    if bytes[4] != VERSION {
+        return Err(anyhow!("Unsupported encryption version {}", bytes[4]));
+    }
+
+    let read_u32 = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+    let params = KdfParams {
+        memory_kib: read_u32(5),
+        iterations: read_u32(9),
+        parallelism: read_u32(13),
+    };
+    params.check_limits()?;
+    let (header, sealed) = bytes.split_at(header_length);
+    let key = params.derive_key(passphrase, &header[17..])?;
+
+    open(&key, header, sealed)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // keeps tests fast, never use outside tests
+    pub const TEST_PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_passphrase_round_trip() {
+        let sealed = encrypt_with_passphrase(b"attack at dawn", "hunter2", TEST_PARAMS).unwrap();
+
+        assert!(is_passphrase_encrypted(&sealed));
+        assert!(!sealed.windows(6).any(|w| w == b"attack"));
+        assert_eq!(
+            decrypt_with_passphrase(&sealed, "hunter2").unwrap(),
+            b"attack at dawn"
+        );
+    }
+
+    #[test]
+    fn test_costly_params_rejected() {
+        let mut sealed =
+            encrypt_with_passphrase(b"attack at dawn", "hunter2", TEST_PARAMS).unwrap();
+        sealed[5..9].copy_from_slice(&0xFFFFFFF0u32.to_be_bytes());
+        let err = decrypt_with_passphrase(&sealed, "hunter2").unwrap_err();
+        assert!(err.to_string().contains("too costly"));
+        sealed[5..9].copy_from_slice(&(MAX_MEMORY_KIB + 1).to_be_bytes());
+        let err = decrypt_with_passphrase(&sealed, "hunter2").unwrap_err();
+        assert!(err.to_string().contains("too costly"));
+
+        let mut sealed =
+            encrypt_with_passphrase(b"attack at dawn", "hunter2", TEST_PARAMS).unwrap();
+        sealed[13..17].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(decrypt_with_passphrase(&sealed, "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let sealed = encrypt_with_passphrase(b"attack at dawn", "hunter2", TEST_PARAMS).unwrap();
+        let err = decrypt_with_passphrase(&sealed, "hunter3").unwrap_err();
+        assert!(err.to_string().contains("wrong key or tampered"));
+    }
+
+    #[test]
+    fn test_tampered_ciphertext() {
+        let mut sealed =
+            encrypt_with_passphrase(b"attack at dawn", "hunter2", TEST_PARAMS).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(decrypt_with_passphrase(&sealed, "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_tampered_header() {
+        let mut sealed =
+            encrypt_with_passphrase(b"attack at dawn", "hunter2", TEST_PARAMS).unwrap();
+        // a different salt derives a different key
+        sealed[20] ^= 1;
+        assert!(decrypt_with_passphrase(&sealed, "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_fresh_salt_and_nonce() {
+        let first = encrypt_with_passphrase(b"same", "same", TEST_PARAMS).unwrap();
+        let second = encrypt_with_passphrase(b"same", "same", TEST_PARAMS).unwrap();
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::known_chunk::{KnownChunk, KnownType};
+use crate::pixels::Image;
+use crate::png::Png;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum ChunkChange {
+    Removed {
+        #[serde(rename = "type")]
+        chunk_type: String,
+        index_a: usize,
+    },
+    Added {
+        #[serde(rename = "type")]
+        chunk_type: String,
+        index_b: usize,
+    },
+    Modified {
+        #[serde(rename = "type")]
+        chunk_type: String,
+        index_a: usize,
+        index_b: usize,
+        details: Vec<String>,
+    },
+    // out of order relative to the other chunks found in both files
+    Moved {
+        #[serde(rename = "type")]
+        chunk_type: String,
+        index_a: usize,
+        index_b: usize,
+    },
+}
+
+// IDAT chunks are compared as one stream, as how the stream is split
+// into chunks carries no meaning
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageDataDiff {
+    pub chunks_a: usize,
+    pub chunks_b: usize,
+    pub bytes_a: usize,
+    pub bytes_b: usize,
+    pub identical: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PixelDiff {
+    pub pixels: usize,
+    pub differing_pixels: usize,
+    pub max_sample_difference: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PngDiff {
+    pub changes: Vec<ChunkChange>,
+    pub image_data: ImageDataDiff,
+}
+
+impl PngDiff {
+    pub fn is_identical(&self) -> bool {
+        self.changes.is_empty() && self.image_data.identical
+    }
+}
+
+// text chunks are matched by keyword as well as type, so a changed value
+// shows up as a modification rather than a removal and an addition
+fn identity(chunk: &Chunk) -> String {
+    let chunk_type = chunk.chunk_type().to_string();
+    match chunk.parse_known() {
+        Ok(KnownChunk::Text(text)) => format!("{} {}", chunk_type, text.keyword),
+        Ok(KnownChunk::Ztxt(ztxt)) => format!("{} {}", chunk_type, ztxt.keyword),
+        Ok(KnownChunk::Itxt(itxt)) => format!("{} {}", chunk_type, itxt.keyword),
+        _ => chunk_type,
+    }
+}
+
+fn field_change<T: PartialEq + std::fmt::Display>(name: &str, a: T, b: T) -> Option<String> {
+    (a != b).then(|| format!("{}: {} -> {}", name, a, b))
+}
+
+// the changed text value and any other changed fields. Text chunks that
+// only differ in how the text was compressed are reported as such
+fn text_changes(keyword: &str, a: &str, b: &str, fields: Vec<Option<String>>) -> Vec<String> {
+    let changes: Vec<String> =
+        std::iter::once((a != b).then(|| format!("{}: {:?} -> {:?}", keyword, a, b)))
+            .chain(fields)
+            .flatten()
+            .collect();
+    if changes.is_empty() {
+        return vec![format!("{}: same text, compressed differently", keyword)];
+    }
+    changes
+}
+
+fn byte_changes(a: &Chunk, b: &Chunk) -> Vec<String> {
+    let first_difference = a
+        .data()
+        .iter()
+        .zip(b.data())
+        .position(|(x, y)| x != y)
+        .unwrap_or(a.length().min(b.length()) as usize);
+    vec![format!(
+        "{} bytes -> {} bytes, first difference at offset {}",
+        a.length(),
+        b.length(),
+        first_difference
+    )]
+}
+
+// what differs between two chunks matched to each other
+fn describe_changes(a: &Chunk, b: &Chunk) -> Vec<String> {
+    match (a.parse_known(), b.parse_known()) {
+        (Ok(KnownChunk::Ihdr(x)), Ok(KnownChunk::Ihdr(y))) => [
+            field_change("width", x.width, y.width),
+            field_change("height", x.height, y.height),
+            field_change("bit depth", x.bit_depth, y.bit_depth),
+            field_change("color type", x.color_type, y.color_type),
+            field_change(
+                "compression method",
+                x.compression_method,
+                y.compression_method,
+            ),
+            field_change("filter method", x.filter_method, y.filter_method),
+            field_change("interlace method", x.interlace_method, y.interlace_method),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        (Ok(KnownChunk::Text(x)), Ok(KnownChunk::Text(y))) => {
+            text_changes(&x.keyword, &x.text, &y.text, vec![])
+        }
+        // compressed text that can't be inflated falls back to the bytes
+        (Ok(KnownChunk::Ztxt(x)), Ok(KnownChunk::Ztxt(y))) => {
+            match (x.decoded_text(), y.decoded_text()) {
+                (Ok(text_x), Ok(text_y)) => text_changes(&x.keyword, &text_x, &text_y, vec![]),
+                _ => byte_changes(a, b),
+            }
+        }
+        (Ok(KnownChunk::Itxt(x)), Ok(KnownChunk::Itxt(y))) => {
+            match (x.decoded_text(), y.decoded_text()) {
+                (Ok(text_x), Ok(text_y)) => text_changes(
+                    &x.keyword,
+                    &text_x,
+                    &text_y,
+                    vec![
+                        field_change("language tag", &x.language_tag, &y.language_tag),
+                        field_change(
+                            "translated keyword",
+                            &x.translated_keyword,
+                            &y.translated_keyword,
+                        ),
+                    ],
+                ),
+                _ => byte_changes(a, b),
+            }
+        }
+        (Ok(KnownChunk::Unknown(_)), _)
+        | (_, Ok(KnownChunk::Unknown(_)))
+        | (Err(_), _)
+        | (_, Err(_)) => byte_changes(a, b),
+        (Ok(x), Ok(y)) => vec![format!("{} -> {}", x, y)],
+    }
+}
+
+// indices into `values` of a longest strictly increasing subsequence
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    let mut lengths = vec![1; values.len()];
+    let mut previous = vec![None; values.len()];
+    for i in 0..values.len() {
+        for j in 0..i {
+            if values[j] < values[i] && lengths[j] + 1 > lengths[i] {
+                lengths[i] = lengths[j] + 1;
+                previous[i] = Some(j);
+            }
+        }
+    }
+
+    let mut sequence = vec![];
+    let mut current = (0..values.len()).max_by_key(|&i| (lengths[i], std::cmp::Reverse(i)));
+    while let Some(i) = current {
+        sequence.push(i);
+        current = previous[i];
+    }
+    sequence.reverse();
+    sequence
+}
+
+pub fn diff_pngs(a: &Png, b: &Png) -> PngDiff {
+    let mut groups: BTreeMap<String, (Vec<usize>, Vec<usize>)> = BTreeMap::new();
+    for (index, chunk) in a.chunks().iter().enumerate() {
+        if chunk.known_type() != Some(KnownType::Idat) {
+            groups.entry(identity(chunk)).or_default().0.push(index);
+        }
+    }
+    for (index, chunk) in b.chunks().iter().enumerate() {
+        if chunk.known_type() != Some(KnownType::Idat) {
+            groups.entry(identity(chunk)).or_default().1.push(index);
+        }
+    }
+
+    let mut changes = vec![];
+    let mut pairs: Vec<(usize, usize)> = vec![];
+    for (a_indices, mut b_indices) in groups.into_values() {
+        // identical chunks pair up first, then the rest in file order
+        let mut unmatched_a = vec![];
+        for index_a in a_indices {
+            let data = a.chunks()[index_a].data();
+            match b_indices
+                .iter()
+                .position(|&index_b| b.chunks()[index_b].data() == data)
+            {
+                Some(position) => pairs.push((index_a, b_indices.remove(position))),
+                None => unmatched_a.push(index_a),
+            }
+        }
+
+        let mut b_left = b_indices.into_iter();
+        for index_a in unmatched_a {
+            match b_left.next() {
+                Some(index_b) => {
+                    let (chunk_a, chunk_b) = (&a.chunks()[index_a], &b.chunks()[index_b]);
+                    changes.push(ChunkChange::Modified {
+                        chunk_type: chunk_a.chunk_type().to_string(),
+                        index_a,
+                        index_b,
+                        details: describe_changes(chunk_a, chunk_b),
+                    });
+                    pairs.push((index_a, index_b));
+                }
+                None => changes.push(ChunkChange::Removed {
+                    chunk_type: a.chunks()[index_a].chunk_type().to_string(),
+                    index_a,
+                }),
+            }
+        }
+        for index_b in b_left {
+            changes.push(ChunkChange::Added {
+                chunk_type: b.chunks()[index_b].chunk_type().to_string(),
+                index_b,
+            });
+        }
+    }
+
+    // chunks outside the longest run kept in order are the ones that moved
+    pairs.sort();
+    let b_order: Vec<usize> = pairs.iter().map(|&(_, index_b)| index_b).collect();
+    let in_order: HashSet<usize> = longest_increasing(&b_order).into_iter().collect();
+    for (position, &(index_a, index_b)) in pairs.iter().enumerate() {
+        if !in_order.contains(&position) {
+            changes.push(ChunkChange::Moved {
+                chunk_type: a.chunks()[index_a].chunk_type().to_string(),
+                index_a,
+                index_b,
+            });
+        }
+    }
+
+    changes.sort_by_key(|change| match change {
+        ChunkChange::Removed { index_a, .. } => (0, *index_a),
+        ChunkChange::Added { index_b, .. } => (1, *index_b),
+        ChunkChange::Modified { index_a, .. } => (2, *index_a),
+        ChunkChange::Moved { index_a, .. } => (3, *index_a),
+    });
+
+    PngDiff {
+        changes,
+        image_data: image_data_diff(a, b),
+    }
+}
+
+fn image_data_diff(a: &Png, b: &Png) -> ImageDataDiff {
+    let stream = |chunks: &[&Chunk]| -> Vec<u8> {
+        chunks
+            .iter()
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    };
+    let (idat_a, idat_b) = (a.image_data_chunks(), b.image_data_chunks());
+    let (stream_a, stream_b) = (stream(&idat_a), stream(&idat_b));
+    ImageDataDiff {
+        chunks_a: idat_a.len(),
+        chunks_b: idat_b.len(),
+        bytes_a: stream_a.len(),
+        bytes_b: stream_b.len(),
+        identical: stream_a == stream_b,
+    }
+}
+
+// both images must decode and share dimensions, colour type and depth
+pub fn diff_pixels(a: &Png, b: &Png) -> Result<PixelDiff> {
+    let image_a = Image::from_png(a)?;
+    let image_b = Image::from_png(b)?;
+    if image_a.header != image_b.header {
+        return Err(anyhow!(
+            "Images differ in size or format, {}x{} color type {} depth {} against {}x{} color type {} depth {}",
+            image_a.header.width,
+            image_a.header.height,
+            image_a.header.color_type,
+            image_a.header.bit_depth,
+            image_b.header.width,
+            image_b.header.height,
+            image_b.header.color_type,
+            image_b.header.bit_depth
+        ));
+    }
+
+    let sample_bytes = image_a.bytes_per_sample();
+    let pixel_bytes = image_a.channels() * sample_bytes;
+    let sample = |bytes: &[u8]| bytes.iter().fold(0u16, |value, &b| value << 8 | b as u16);
+
+    let mut differing_pixels = 0;
+    let mut max_sample_difference = 0;
+    for (pixel_a, pixel_b) in image_a
+        .data
+        .chunks_exact(pixel_bytes)
+        .zip(image_b.data.chunks_exact(pixel_bytes))
+    {
+        if pixel_a == pixel_b {
+            continue;
+        }
+        differing_pixels += 1;
+        for (sample_a, sample_b) in pixel_a
+            .chunks_exact(sample_bytes)
+            .zip(pixel_b.chunks_exact(sample_bytes))
+        {
+            max_sample_difference =
+                max_sample_difference.max(sample(sample_a).abs_diff(sample(sample_b)));
+        }
+    }
+
+    Ok(PixelDiff {
+        pixels: image_a.pixel_count(),
+        differing_pixels,
+        max_sample_difference,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::stego::{self, LsbOptions};
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    const PNG_FILE: &[u8] = include_bytes!("../capy.png");
+
+    fn capy() -> Png {
+        Png::try_from(PNG_FILE).unwrap()
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_identical() {
+        let diff = diff_pngs(&capy(), &capy());
+        assert!(diff.is_identical());
+    }
+
+    #[test]
+    fn test_added_removed_and_text_change() {
+        let a = capy();
+        let mut b = capy();
+        b.remove_first_chunk("bKGD").unwrap();
+        b.append_chunk(chunk("ruSt", b"new"));
+        let index = b.indices_of("tEXt")[0];
+        let text = b.chunks()[index].parse_known().unwrap();
+        let KnownChunk::Text(text) = text else {
+            panic!("Not a text chunk")
+        };
+        let replacement = format!("{}\0changed", text.keyword);
+        b.replace_chunk(index, chunk("tEXt", replacement.as_bytes()))
+            .unwrap();
+
+        let diff = diff_pngs(&a, &b);
+        assert!(diff.image_data.identical);
+        assert_eq!(diff.changes.len(), 3);
+        assert!(
+            matches!(&diff.changes[0], ChunkChange::Removed { chunk_type, .. } if chunk_type == "bKGD")
+        );
+        assert!(
+            matches!(&diff.changes[1], ChunkChange::Added { chunk_type, .. } if chunk_type == "ruSt")
+        );
+        let ChunkChange::Modified { details, .. } = &diff.changes[2] else {
+            panic!("Expected a modification, got {:?}", diff.changes[2]);
+        };
+        assert_eq!(
+            details,
+            &vec![format!("{}: {:?} -> \"changed\"", text.keyword, text.text)]
+        );
+    }
+
+    fn deflate(text: &str) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn compressed_text_details(
+        chunk_type: &str,
+        prefix: &[u8],
+        old: &str,
+        new: &str,
+    ) -> Vec<String> {
+        let with_text = |text: &str| {
+            let mut png = capy();
+            let data = [prefix, &deflate(text)].concat();
+            png.append_chunk(chunk(chunk_type, &data));
+            png
+        };
+
+        let diff = diff_pngs(&with_text(old), &with_text(new));
+        assert_eq!(diff.changes.len(), 1);
+        let ChunkChange::Modified { details, .. } = &diff.changes[0] else {
+            panic!("Expected a modification, got {:?}", diff.changes[0]);
+        };
+        details.clone()
+    }
+
+    #[test]
+    fn test_compressed_text_change() {
+        let details = compressed_text_details("zTXt", b"Comment\0\0", "before", "after");
+        assert_eq!(details, vec![r#"Comment: "before" -> "after""#]);
+
+        let details = compressed_text_details("iTXt", b"Title\0\x01\0en\0\0", "vorher", "nachher");
+        assert_eq!(details, vec![r#"Title: "vorher" -> "nachher""#]);
+    }
+
+    #[test]
+    fn test_ihdr_fields() {
+        let a = capy();
+        let mut b = capy();
+        b.set_keep_unsafe(true);
+        let mut ihdr = b.chunk_data_mut(0).unwrap();
+        ihdr[3] = ihdr[3].wrapping_add(1);
+        ihdr.finish().unwrap();
+
+        let diff = diff_pngs(&a, &b);
+        assert_eq!(diff.changes.len(), 1);
+        let ChunkChange::Modified { details, .. } = &diff.changes[0] else {
+            panic!("Expected a modification, got {:?}", diff.changes[0]);
+        };
+        assert_eq!(details.len(), 1);
+        assert!(details[0].starts_with("width: "));
+    }
+
+    #[test]
+    fn test_moved() {
+        let a = capy();
+        let mut b = capy();
+        let last = b.indices_of("tIME")[0];
+        b.move_chunk(last, 1).unwrap();
+
+        let diff = diff_pngs(&a, &b);
+        assert_eq!(
+            diff.changes,
+            vec![ChunkChange::Moved {
+                chunk_type: "tIME".to_string(),
+                index_a: last,
+                index_b: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pixel_diff() {
+        let a = capy();
+        let mut b = capy();
+        let mut image = Image::from_png(&b).unwrap();
+        stego::embed(&mut image, b"a few bits", &LsbOptions::default()).unwrap();
+        image.write_to(&mut b).unwrap();
+
+        let diff = diff_pngs(&a, &b);
+        assert!(!diff.image_data.identical);
+        let pixels = diff_pixels(&a, &b).unwrap();
+        assert!(pixels.differing_pixels > 0);
+        assert_eq!(pixels.max_sample_difference, 1);
+        assert_eq!(diff_pixels(&a, &a).unwrap().differing_pixels, 0);
+    }
+}
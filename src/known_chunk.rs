@@ -0,0 +1,637 @@
+use core::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+// chunk types with a typed parser below, in the order they are
+// defined in the PNG spec
+pub const KNOWN_CHUNK_TYPES: [&str; 11] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "gAMA", "sRGB", "tEXt", "zTXt", "iTXt", "pHYs", "tIME",
+];
+
+// every chunk type registered in the PNG spec and its registered
+// extensions, parsed or not. These are understood well enough that an
+// edit to the critical chunks never makes them meaningless
+pub const REGISTERED_CHUNK_TYPES: [&str; 31] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCv",
+    "cLLI", "tEXt", "zTXt", "iTXt", "bKGD", "hIST", "pHYs", "sPLT", "eXIf", "tIME", "acTL", "fcTL",
+    "fdAT", "oFFs", "pCAL", "sCAL", "gIFg", "gIFx", "sTER",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plte {
+    pub entries: Vec<[u8; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gama {
+    pub gamma: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Srgb {
+    pub rendering_intent: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text {
+    pub keyword: String,
+    pub text: String,
+}
+
+// compressed text is kept compressed, the keyword alone is
+// enough to identify it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ztxt {
+    pub keyword: String,
+    pub compression_method: u8,
+    pub compressed_text: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Itxt {
+    pub keyword: String,
+    pub compression_flag: u8,
+    pub compression_method: u8,
+    pub language_tag: String,
+    pub translated_keyword: String,
+    pub text: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phys {
+    pub pixels_per_unit_x: u32,
+    pub pixels_per_unit_y: u32,
+    pub unit: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+// which known chunk a chunk type is, decided from the type alone so
+// callers can match on it without parsing or copying the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownType {
+    Ihdr,
+    Plte,
+    Idat,
+    Iend,
+    Gama,
+    Srgb,
+    Text,
+    Ztxt,
+    Itxt,
+    Phys,
+    Time,
+}
+
+impl KnownType {
+    pub fn of(chunk_type: &ChunkType) -> Option<KnownType> {
+        let known_type = match &chunk_type.bytes() {
+            b"IHDR" => KnownType::Ihdr,
+            b"PLTE" => KnownType::Plte,
+            b"IDAT" => KnownType::Idat,
+            b"IEND" => KnownType::Iend,
+            b"gAMA" => KnownType::Gama,
+            b"sRGB" => KnownType::Srgb,
+            b"tEXt" => KnownType::Text,
+            b"zTXt" => KnownType::Ztxt,
+            b"iTXt" => KnownType::Itxt,
+            b"pHYs" => KnownType::Phys,
+            b"tIME" => KnownType::Time,
+            _ => return None,
+        };
+        Some(known_type)
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, KnownType::Text | KnownType::Ztxt | KnownType::Itxt)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum KnownChunk {
+    Ihdr(Ihdr),
+    Plte(Plte),
+    Idat(Vec<u8>),
+    Iend,
+    Gama(Gama),
+    Srgb(Srgb),
+    Text(Text),
+    Ztxt(Ztxt),
+    Itxt(Itxt),
+    Phys(Phys),
+    Time(Time),
+    Unknown(Chunk),
+}
+
+impl KnownChunk {
+    pub fn is_known(chunk_type: &str) -> bool {
+        KNOWN_CHUNK_TYPES.contains(&chunk_type)
+    }
+
+    pub fn is_registered(chunk_type: &str) -> bool {
+        REGISTERED_CHUNK_TYPES.contains(&chunk_type)
+    }
+
+    pub fn from_chunk(chunk: &Chunk) -> Result<KnownChunk> {
+        let data = chunk.data();
+        let Some(known_type) = chunk.known_type() else {
+            return Ok(KnownChunk::Unknown(chunk.clone()));
+        };
+        let known = match known_type {
+            KnownType::Ihdr => KnownChunk::Ihdr(Ihdr::try_from(data)?),
+            KnownType::Plte => KnownChunk::Plte(Plte::try_from(data)?),
+            KnownType::Idat => KnownChunk::Idat(data.to_vec()),
+            KnownType::Iend => {
+                if !data.is_empty() {
+                    return Err(anyhow!("IEND chunk must be empty"));
+                }
+                KnownChunk::Iend
+            }
+            KnownType::Gama => KnownChunk::Gama(Gama::try_from(data)?),
+            KnownType::Srgb => KnownChunk::Srgb(Srgb::try_from(data)?),
+            KnownType::Text => KnownChunk::Text(Text::try_from(data)?),
+            KnownType::Ztxt => KnownChunk::Ztxt(Ztxt::try_from(data)?),
+            KnownType::Itxt => KnownChunk::Itxt(Itxt::try_from(data)?),
+            KnownType::Phys => KnownChunk::Phys(Phys::try_from(data)?),
+            KnownType::Time => KnownChunk::Time(Time::try_from(data)?),
+        };
+
+        Ok(known)
+    }
+}
+
+fn expect_length(name: &str, data: &[u8], expected: usize) -> Result<()> {
+    if data.len() != expected {
+        return Err(anyhow!(
+            "{} chunk must be {} bytes, found {}",
+            name,
+            expected,
+            data.len()
+        ));
+    }
+    Ok(())
+}
+
+fn read_u32(data: &[u8], start: usize) -> u32 {
+    u32::from_be_bytes(data[start..start + 4].try_into().unwrap())
+}
+
+// keywords are 1-79 bytes of Latin-1 followed by a null separator
+fn split_keyword(data: &[u8]) -> Result<(String, &[u8])> {
+    let null_index = data
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| anyhow!("Keyword is missing null separator"))?;
+
+    if null_index == 0 || null_index > 79 {
+        return Err(anyhow!("Keyword must be between 1 and 79 bytes"));
+    }
+
+    let keyword = latin1_to_string(&data[..null_index]);
+    Ok((keyword, &data[null_index + 1..]))
+}
+
+fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+// decompressed text is capped, so a small chunk can't inflate into
+// gigabytes when it is read
+const MAX_TEXT_LENGTH: usize = 1 << 20;
+
+fn inflate_text(compression_method: u8, compressed: &[u8]) -> Result<Vec<u8>> {
+    if compression_method != 0 {
+        return Err(anyhow!(
+            "Unknown text compression method {}",
+            compression_method
+        ));
+    }
+
+    let mut text = vec![];
+    ZlibDecoder::new(compressed)
+        .take(MAX_TEXT_LENGTH as u64 + 1)
+        .read_to_end(&mut text)
+        .map_err(|e| anyhow!("Could not decompress text: {}", e))?;
+    if text.len() > MAX_TEXT_LENGTH {
+        return Err(anyhow!(
+            "Compressed text is over the {} byte limit",
+            MAX_TEXT_LENGTH
+        ));
+    }
+    Ok(text)
+}
+
+impl Ztxt {
+    pub fn decoded_text(&self) -> Result<String> {
+        let text = inflate_text(self.compression_method, &self.compressed_text)?;
+        Ok(latin1_to_string(&text))
+    }
+}
+
+impl Itxt {
+    // the text, decompressed if it is stored compressed
+    pub fn decoded_text(&self) -> Result<String> {
+        let text = match self.compression_flag {
+            0 => self.text.clone(),
+            _ => inflate_text(self.compression_method, &self.text)?,
+        };
+        Ok(String::from_utf8(text)?)
+    }
+}
+
+impl TryFrom<&[u8]> for Ihdr {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        expect_length("IHDR", data, 13)?;
+
+        let ihdr = Ihdr {
+            width: read_u32(data, 0),
+            height: read_u32(data, 4),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: data[12],
+        };
+
+        if ihdr.width == 0 || ihdr.height == 0 {
+            return Err(anyhow!("IHDR dimensions must be non-zero"));
+        }
+
+        let allowed_depths: &[u8] = match ihdr.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            other => return Err(anyhow!("IHDR color type {} not valid", other)),
+        };
+        if !allowed_depths.contains(&ihdr.bit_depth) {
+            return Err(anyhow!(
+                "IHDR bit depth {} not valid for color type {}",
+                ihdr.bit_depth,
+                ihdr.color_type
+            ));
+        }
+
+        Ok(ihdr)
+    }
+}
+
+impl TryFrom<&[u8]> for Plte {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 256 * 3 {
+            return Err(anyhow!("PLTE length must be a multiple of 3 up to 768"));
+        }
+
+        let entries = data
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        Ok(Plte { entries })
+    }
+}
+
+impl TryFrom<&[u8]> for Gama {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        expect_length("gAMA", data, 4)?;
+        Ok(Gama {
+            gamma: read_u32(data, 0),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Srgb {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        expect_length("sRGB", data, 1)?;
+        Ok(Srgb {
+            rendering_intent: data[0],
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Text {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        let (keyword, rest) = split_keyword(data)?;
+        Ok(Text {
+            keyword,
+            text: latin1_to_string(rest),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Ztxt {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        let (keyword, rest) = split_keyword(data)?;
+        if rest.is_empty() {
+            return Err(anyhow!("zTXt chunk is missing compression method"));
+        }
+
+        Ok(Ztxt {
+            keyword,
+            compression_method: rest[0],
+            compressed_text: rest[1..].to_vec(),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Itxt {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        let (keyword, rest) = split_keyword(data)?;
+        if rest.len() < 2 {
+            return Err(anyhow!("iTXt chunk is missing compression fields"));
+        }
+        let compression_flag = rest[0];
+        let compression_method = rest[1];
+
+        let mut fields = rest[2..].splitn(3, |b| *b == 0);
+        let language_tag = fields.next().unwrap_or_default();
+        let translated_keyword = fields
+            .next()
+            .ok_or_else(|| anyhow!("iTXt chunk is missing language tag separator"))?;
+        let text = fields
+            .next()
+            .ok_or_else(|| anyhow!("iTXt chunk is missing translated keyword separator"))?;
+
+        Ok(Itxt {
+            keyword,
+            compression_flag,
+            compression_method,
+            language_tag: latin1_to_string(language_tag),
+            translated_keyword: String::from_utf8(translated_keyword.to_vec())?,
+            text: text.to_vec(),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Phys {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        expect_length("pHYs", data, 9)?;
+        Ok(Phys {
+            pixels_per_unit_x: read_u32(data, 0),
+            pixels_per_unit_y: read_u32(data, 4),
+            unit: data[8],
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Time {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        expect_length("tIME", data, 7)?;
+        Ok(Time {
+            year: u16::from_be_bytes([data[0], data[1]]),
+            month: data[2],
+            day: data[3],
+            hour: data[4],
+            minute: data[5],
+            second: data[6],
+        })
+    }
+}
+
+impl Display for KnownChunk {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            KnownChunk::Ihdr(h) => write!(
+                f,
+                "{}x{} depth={} color={} interlace={}",
+                h.width, h.height, h.bit_depth, h.color_type, h.interlace_method
+            ),
+            KnownChunk::Plte(p) => write!(f, "{} palette entries", p.entries.len()),
+            KnownChunk::Idat(d) => write!(f, "{} bytes of image data", d.len()),
+            KnownChunk::Iend => write!(f, "end of image"),
+            KnownChunk::Gama(g) => write!(f, "gamma={}", g.gamma as f64 / 100000.0),
+            KnownChunk::Srgb(s) => write!(f, "rendering intent={}", s.rendering_intent),
+            KnownChunk::Text(t) => write!(f, "{}={}", t.keyword, t.text),
+            KnownChunk::Ztxt(z) => write!(
+                f,
+                "{} ({} compressed bytes)",
+                z.keyword,
+                z.compressed_text.len()
+            ),
+            KnownChunk::Itxt(i) => {
+                if i.compression_flag == 0 {
+                    write!(f, "{}={}", i.keyword, String::from_utf8_lossy(&i.text))
+                } else {
+                    write!(f, "{} ({} compressed bytes)", i.keyword, i.text.len())
+                }
+            }
+            KnownChunk::Phys(p) => write!(
+                f,
+                "{}x{} pixels per {}",
+                p.pixels_per_unit_x,
+                p.pixels_per_unit_y,
+                if p.unit == 1 { "metre" } else { "unit" }
+            ),
+            KnownChunk::Time(t) => write!(
+                f,
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                t.year, t.month, t.day, t.hour, t.minute, t.second
+            ),
+            KnownChunk::Unknown(c) => write!(f, "{} bytes of {} data", c.length(), c.chunk_type()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    fn chunk_from_bytes(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_ihdr() {
+        let data = [0, 0, 1, 0, 0, 0, 0, 128, 8, 6, 0, 0, 0];
+        let chunk = chunk_from_bytes("IHDR", &data);
+
+        match chunk.parse_known().unwrap() {
+            KnownChunk::Ihdr(ihdr) => {
+                assert_eq!(ihdr.width, 256);
+                assert_eq!(ihdr.height, 128);
+                assert_eq!(ihdr.bit_depth, 8);
+                assert_eq!(ihdr.color_type, 6);
+            }
+            other => panic!("Expected IHDR, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_ihdr() {
+        let data = [0, 0, 1, 0, 0, 0, 0, 128, 3, 6, 0, 0, 0];
+        let chunk = chunk_from_bytes("IHDR", &data);
+        assert!(chunk.parse_known().is_err());
+
+        let chunk = chunk_from_bytes("IHDR", &data[..12]);
+        assert!(chunk.parse_known().is_err());
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let chunk = chunk_from_bytes("tEXt", b"Author\0Capybara");
+
+        match chunk.parse_known().unwrap() {
+            KnownChunk::Text(text) => {
+                assert_eq!(text.keyword, "Author");
+                assert_eq!(text.text, "Capybara");
+            }
+            other => panic!("Expected tEXt, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_text_missing_keyword() {
+        let chunk = chunk_from_bytes("tEXt", b"\0no keyword");
+        assert!(chunk.parse_known().is_err());
+    }
+
+    #[test]
+    fn test_parse_itxt() {
+        let chunk = chunk_from_bytes("iTXt", b"Title\0\0\0en\0Titel\0hello");
+
+        match chunk.parse_known().unwrap() {
+            KnownChunk::Itxt(itxt) => {
+                assert_eq!(itxt.keyword, "Title");
+                assert_eq!(itxt.language_tag, "en");
+                assert_eq!(itxt.translated_keyword, "Titel");
+                assert_eq!(itxt.text, b"hello");
+            }
+            other => panic!("Expected iTXt, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let chunk = chunk_from_bytes("tIME", &[7, 232, 10, 19, 12, 30, 5]);
+        let known = chunk.parse_known().unwrap();
+
+        assert!(matches!(known, KnownChunk::Time(Time { year: 2024, .. })));
+        assert_eq!(known.to_string(), "2024-10-19 12:30:05");
+    }
+
+    #[test]
+    fn test_decoded_text() {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"inflated").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let chunk = chunk_from_bytes("zTXt", &[b"Comment\0\0", &compressed[..]].concat());
+        let KnownChunk::Ztxt(ztxt) = chunk.parse_known().unwrap() else {
+            panic!("Not parsed as zTXt");
+        };
+        assert_eq!(ztxt.decoded_text().unwrap(), "inflated");
+
+        let chunk = chunk_from_bytes("iTXt", &[b"Title\0\x01\0en\0\0", &compressed[..]].concat());
+        let KnownChunk::Itxt(itxt) = chunk.parse_known().unwrap() else {
+            panic!("Not parsed as iTXt");
+        };
+        assert_eq!(itxt.decoded_text().unwrap(), "inflated");
+
+        let chunk = chunk_from_bytes("zTXt", &[b"Comment\0\x05", &compressed[..]].concat());
+        let KnownChunk::Ztxt(ztxt) = chunk.parse_known().unwrap() else {
+            panic!("Not parsed as zTXt");
+        };
+        assert!(ztxt.decoded_text().is_err());
+
+        // text past the limit is refused rather than inflated in full
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&vec![b'a'; MAX_TEXT_LENGTH + 1]).unwrap();
+        let bomb = Ztxt {
+            keyword: "Comment".to_string(),
+            compression_method: 0,
+            compressed_text: encoder.finish().unwrap(),
+        };
+        assert!(bomb
+            .decoded_text()
+            .unwrap_err()
+            .to_string()
+            .contains("limit"));
+    }
+
+    #[test]
+    fn test_parse_iend() {
+        let chunk = chunk_from_bytes("IEND", &[]);
+        assert!(matches!(chunk.parse_known().unwrap(), KnownChunk::Iend));
+
+        let chunk = chunk_from_bytes("IEND", &[1]);
+        assert!(chunk.parse_known().is_err());
+    }
+
+    #[test]
+    fn test_unknown_fallback() {
+        let chunk = chunk_from_bytes("ruSt", b"secret");
+
+        match chunk.parse_known().unwrap() {
+            KnownChunk::Unknown(c) => assert_eq!(c.data(), b"secret"),
+            other => panic!("Expected unknown chunk, got {:?}", other),
+        }
+        assert!(!KnownChunk::is_known("ruSt"));
+        assert!(KnownChunk::is_known("pHYs"));
+        assert!(KnownChunk::is_registered("tRNS"));
+        assert!(!KnownChunk::is_registered("ruSt"));
+        assert_eq!(chunk.known_type(), None);
+    }
+
+    #[test]
+    fn test_known_type() {
+        // a known type without valid data still has its type
+        let chunk = chunk_from_bytes("IHDR", &[1, 2]);
+        assert_eq!(chunk.known_type(), Some(KnownType::Ihdr));
+        assert!(chunk.parse_known().is_err());
+
+        for chunk_type in KNOWN_CHUNK_TYPES {
+            assert!(KnownType::of(&ChunkType::from_str(chunk_type).unwrap()).is_some());
+        }
+        let itxt = chunk_from_bytes("iTXt", b"");
+        assert!(itxt.known_type().unwrap().is_text());
+        assert!(!KnownType::Time.is_text());
+    }
+}
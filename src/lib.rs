@@ -0,0 +1,18 @@
+pub mod args;
+pub mod batch;
+pub mod chunk;
+pub mod chunk_type;
+pub mod codec;
+pub mod commands;
+pub mod crypto;
+pub mod diff;
+pub mod known_chunk;
+pub mod output;
+pub mod payload;
+pub mod pixels;
+pub mod png;
+pub mod recipients;
+pub mod report;
+pub mod signature;
+pub mod stego;
+pub mod strip;
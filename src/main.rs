@@ -1,36 +1,85 @@
-mod args;
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
+use anyhow::anyhow;
+use clap::{CommandFactory, Parser};
+use pnglee::args::Args;
+use pnglee::batch;
+use pnglee::output::{emit, error_code, ErrorCode, Format, OutputArgs, Rendered, WithCode};
+use std::process::ExitCode;
 
-use anyhow::Result;
+pub type Error = Box<dyn std::error::Error>;
 
-use args::Args;
-use clap::Parser;
-use commands::Command;
+// clap prints its own usage errors as text, so under --format json or
+// ndjson they are reported as a usage error object instead. Help and
+// version output is left to clap
+fn usage_error(error: clap::Error) -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let format = OutputArgs::format_in(&args);
+    if format == Format::Text || !error.use_stderr() {
+        error.exit();
+    }
 
-pub type Error = Box<dyn std::error::Error>;
+    let name = args
+        .iter()
+        .skip(1)
+        .find_map(|arg| {
+            Args::command()
+                .find_subcommand(arg)
+                .map(|c| c.get_name().to_string())
+        })
+        .unwrap_or_else(|| "pnglee".to_string());
+    // the first paragraph, without the usage and tips that follow it
+    let rendered = error.render().to_string();
+    let message = rendered
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let message = message.strip_prefix("error: ").unwrap_or(&message);
+    let result: anyhow::Result<Rendered> = Err(anyhow!("{}", message)).with_code(ErrorCode::Usage);
+    let output = OutputArgs {
+        format,
+        quiet: false,
+    };
+    let _ = emit(&output, &name, result);
+    ExitCode::from(ErrorCode::Usage.exit_code())
+}
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    match args.command {
-        Command::Encode => return Command::handle_encode(args),
-        Command::Decode => {
-            let _ = Command::handle_decode(args);
-            return Ok(());
+fn main() -> ExitCode {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(error) => return usage_error(error),
+    };
+    let output = args.output;
+    let name = args.command.name();
+    let result = match args.command.input() {
+        Some((file_path, batch)) if batch::is_batch(file_path, batch) => {
+            batch::run(&output, &args.command)
         }
-        Command::Remove => return Command::handle_remove(args),
-        Command::Print => {
-            let _ = Command::handle_print(args);
-            return Ok(());
+        _ => emit(&output, name, args.command.run()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            // structured formats have already reported the error on stdout
+            if output.format == Format::Text {
+                eprintln!("Error: {:#}", error);
+            }
+            ExitCode::from(error_code(&error).exit_code())
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{args::Args, commands::Command};
+    use clap::Parser;
+    use pnglee::output::{error_code, ErrorCode};
+    use pnglee::{args::Args, commands::Command};
+    use std::fs;
+
+    fn parse(args: &[&str]) -> Command {
+        Args::parse_from(std::iter::once("pnglee").chain(args.iter().copied())).command
+    }
 
     #[test]
     fn itest() {
@@ -39,45 +88,119 @@ mod tests {
         let test_msg = "my test message";
         let output_path = "./test-capy.png";
 
-        let encode_args = Args {
-            command: Command::Encode,
-            file_path: Some(String::from(input_path)),
-            chunk_type: Some(String::from(chunk_type)),
-            message: Some(String::from(test_msg)),
-            output_file: Some(String::from(output_path)),
+        let Command::Encode(encode_args) = parse(&[
+            "encode",
+            input_path,
+            chunk_type,
+            test_msg,
+            "-o",
+            output_path,
+        ]) else {
+            unreachable!()
         };
         Command::handle_encode(encode_args).unwrap();
 
-        let decode_args = Args {
-            command: Command::Decode,
-            file_path: Some(String::from(output_path)),
-            chunk_type: Some(String::from(chunk_type)),
-            message: None,
-            output_file: None,
+        let Command::Decode(decode_args) = parse(&["decode", output_path, chunk_type]) else {
+            unreachable!()
         };
         let decode_res = Command::handle_decode(decode_args.clone()).unwrap();
-        assert!(decode_res == test_msg);
-
-        let print_args = Args {
-            command: Command::Print,
-            file_path: Some(String::from(output_path)),
-            chunk_type: None,
-            message: None,
-            output_file: None,
+        assert_eq!(decode_res.chunks.len(), 1);
+        assert_eq!(decode_res.payload.message.as_deref(), Some(test_msg));
+
+        let Command::Print(print_args) = parse(&["print", output_path]) else {
+            unreachable!()
         };
         let print_res = Command::handle_print(print_args).unwrap();
-        assert!(print_res == vec![test_msg]);
-
-        let remove_args = Args {
-            command: Command::Remove,
-            file_path: Some(String::from(output_path)),
-            chunk_type: Some(String::from(chunk_type)),
-            message: None,
-            output_file: None,
+        assert!(print_res.descriptions == vec![test_msg]);
+        assert!(print_res.chunks.iter().any(|c| c.chunk_type == chunk_type));
+
+        let Command::Remove(remove_args) = parse(&["remove", output_path, chunk_type]) else {
+            unreachable!()
+        };
+        Command::handle_remove(remove_args).unwrap();
+
+        let err = Command::handle_decode(decode_args).unwrap_err();
+        assert_eq!(error_code(&err), ErrorCode::NotFound);
+    }
+
+    #[test]
+    fn itest_binary_payload() {
+        let dir = std::env::temp_dir();
+        let payload_path = dir.join("pnglee-payload.bin");
+        let output_path = dir.join("pnglee-binary-capy.png");
+        let decoded_path = dir.join("pnglee-decoded.bin");
+        let payload: Vec<u8> = (0..=255).rev().collect();
+        fs::write(&payload_path, &payload).unwrap();
+
+        let Command::Encode(encode_args) = parse(&[
+            "encode",
+            "./capy.png",
+            "biNa",
+            "--file",
+            payload_path.to_str().unwrap(),
+            "--mime",
+            "application/octet-stream",
+            "--chunk-size",
+            "100",
+            "--output",
+            output_path.to_str().unwrap(),
+        ]) else {
+            unreachable!()
         };
-        let _ = Command::handle_remove(remove_args).unwrap();
+        Command::handle_encode(encode_args).unwrap();
 
-        let decode_res = Command::handle_decode(decode_args).unwrap();
-        assert!(decode_res == String::from(""));
+        let Command::Decode(decode_args) =
+            parse(&["decode", output_path.to_str().unwrap(), "biNa"])
+        else {
+            unreachable!()
+        };
+        assert!(Command::handle_decode(decode_args).is_err());
+
+        let Command::Decode(decode_args) = parse(&[
+            "decode",
+            output_path.to_str().unwrap(),
+            "biNa",
+            "--out",
+            decoded_path.to_str().unwrap(),
+        ]) else {
+            unreachable!()
+        };
+        Command::handle_decode(decode_args).unwrap();
+        assert_eq!(fs::read(&decoded_path).unwrap(), payload);
+    }
+
+    #[test]
+    fn itest_repair_trailing_data() {
+        let dir = std::env::temp_dir();
+        let damaged_path = dir.join("pnglee-trailing-capy.png");
+        let repaired_path = dir.join("pnglee-repaired-capy.png");
+        let original = fs::read("./capy.png").unwrap();
+        let mut damaged = original.clone();
+        damaged.extend_from_slice(b"appended garbage");
+        fs::write(&damaged_path, &damaged).unwrap();
+
+        let Command::Repair(repair_args) = parse(&[
+            "repair",
+            damaged_path.to_str().unwrap(),
+            "-o",
+            repaired_path.to_str().unwrap(),
+        ]) else {
+            unreachable!()
+        };
+        let report = Command::handle_repair(repair_args).unwrap();
+        assert_eq!(report.written.stripped_trailing_bytes, 16);
+        assert_eq!(fs::read(&repaired_path).unwrap(), original);
+
+        let Command::Repair(repair_args) = parse(&[
+            "repair",
+            damaged_path.to_str().unwrap(),
+            "-o",
+            repaired_path.to_str().unwrap(),
+            "--keep-trailing",
+        ]) else {
+            unreachable!()
+        };
+        Command::handle_repair(repair_args).unwrap();
+        assert_eq!(fs::read(&repaired_path).unwrap(), damaged);
     }
 }
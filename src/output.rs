@@ -0,0 +1,340 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::Write;
+
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+
+use anyhow::Result;
+
+#[derive(clap::Args, Debug, Clone, Default, PartialEq)]
+pub struct OutputArgs {
+    /// How to print results and errors
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Only print results, without progress messages
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+}
+
+impl OutputArgs {
+    // the format asked for on a command line clap rejected, so its usage
+    // error can still be reported in that format. The last one given wins,
+    // as it would for a command line that parses
+    pub fn format_in(args: &[String]) -> Format {
+        let mut format = Format::Text;
+        let mut args = args.iter().take_while(|arg| *arg != "--");
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix("--format") {
+                Some("") => args.next().map(String::as_str),
+                Some(rest) => rest.strip_prefix('='),
+                None => None,
+            };
+            if let Some(parsed) = value.and_then(|v| Format::from_str(v, false).ok()) {
+                format = parsed;
+            }
+        }
+        format
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Text,
+    // one pretty printed object per command
+    Json,
+    // one compact object per line, for streams of results
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Usage,
+    NotFound,
+    Io,
+    InvalidPng,
+    InvalidPayload,
+    InvalidKey,
+    Decryption,
+    Verification,
+    Capacity,
+    Other,
+}
+
+impl ErrorCode {
+    // 2 matches clap's own exit code for bad arguments
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ErrorCode::Other => 1,
+            ErrorCode::Usage => 2,
+            ErrorCode::Io => 3,
+            ErrorCode::InvalidPng => 4,
+            ErrorCode::NotFound => 5,
+            ErrorCode::InvalidPayload => 6,
+            ErrorCode::InvalidKey => 7,
+            ErrorCode::Decryption => 8,
+            ErrorCode::Verification => 9,
+            ErrorCode::Capacity => 10,
+        }
+    }
+}
+
+// a command's result, printed in full as text, serialised for --format
+// json, or cut down to the bare result for --quiet
+pub trait Report: Serialize + Display {
+    fn quiet(&self) -> Option<String> {
+        None
+    }
+
+    // bytes written to stdout as they are in place of the text output
+    fn raw(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+// a report already rendered in each form it can be printed in, so the
+// reports of different commands can be handled alike
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    text: String,
+    quiet: Option<String>,
+    raw: Option<Vec<u8>>,
+    value: Value,
+}
+
+impl Rendered {
+    pub fn new<R: Report>(report: &R) -> Result<Rendered> {
+        Ok(Rendered {
+            text: report.to_string(),
+            quiet: report.quiet(),
+            raw: report.raw().map(|bytes| bytes.to_vec()),
+            value: serde_json::to_value(report)?,
+        })
+    }
+}
+
+impl Serialize for Rendered {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl Display for Rendered {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Report for Rendered {
+    fn quiet(&self) -> Option<String> {
+        self.quiet.clone()
+    }
+
+    fn raw(&self) -> Option<&[u8]> {
+        self.raw.as_deref()
+    }
+}
+
+// an error tagged with a code that scripts can match on
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    error: anyhow::Error,
+}
+
+impl Display for CodedError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for CodedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+pub trait WithCode<T> {
+    fn with_code(self, code: ErrorCode) -> Result<T>;
+}
+
+// an error that already has a code keeps it, as the innermost code is
+// the most specific
+impl<T, E: Into<anyhow::Error>> WithCode<T> for std::result::Result<T, E> {
+    fn with_code(self, code: ErrorCode) -> Result<T> {
+        self.map_err(|e| {
+            let error = e.into();
+            if error.downcast_ref::<CodedError>().is_some() {
+                error
+            } else {
+                anyhow::Error::new(CodedError { code, error })
+            }
+        })
+    }
+}
+
+// untagged io errors, including missing files, are Io. NotFound is kept
+// for chunks and payloads that are not in the file
+pub fn error_code(error: &anyhow::Error) -> ErrorCode {
+    for cause in error.chain() {
+        if let Some(coded) = cause.downcast_ref::<CodedError>() {
+            return coded.code;
+        }
+        if cause.is::<io::Error>() {
+            return ErrorCode::Io;
+        }
+    }
+    ErrorCode::Other
+}
+
+pub fn envelope<R: Serialize>(command: &str, result: &Result<R>) -> Value {
+    match result {
+        Ok(report) => json!({ "command": command, "ok": true, "result": report }),
+        Err(error) => json!({
+            "command": command,
+            "ok": false,
+            "error": { "code": error_code(error), "message": format!("{:#}", error) },
+        }),
+    }
+}
+
+// prints the result in the chosen format, handing any error back so the
+// caller still fails
+pub fn emit<R: Report>(output: &OutputArgs, command: &str, result: Result<R>) -> Result<()> {
+    let value = match output.format {
+        Format::Text => {
+            let report = result?;
+            match (report.raw(), output.quiet) {
+                (Some(bytes), _) => io::stdout().write_all(bytes)?,
+                (None, true) => {
+                    if let Some(quiet) = report.quiet() {
+                        println!("{}", quiet);
+                    }
+                }
+                (None, false) => print!("{}", report),
+            }
+            return Ok(());
+        }
+        Format::Json => serde_json::to_string_pretty(&envelope(command, &result))?,
+        Format::Ndjson => serde_json::to_string(&envelope(command, &result))?,
+    };
+    println!("{}", value);
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_error_codes() {
+        let tagged: Result<()> = Err(anyhow!("Bad key")).with_code(ErrorCode::InvalidKey);
+        let tagged = tagged.unwrap_err();
+        assert_eq!(error_code(&tagged), ErrorCode::InvalidKey);
+        assert_eq!(tagged.to_string(), "Bad key");
+
+        let retagged: Result<()> = Err(tagged).with_code(ErrorCode::Other);
+        assert_eq!(error_code(&retagged.unwrap_err()), ErrorCode::InvalidKey);
+
+        let missing = std::fs::read("/no/such/pnglee/file").unwrap_err();
+        assert_eq!(error_code(&missing.into()), ErrorCode::Io);
+        assert_eq!(error_code(&anyhow!("Something")), ErrorCode::Other);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let ok: Result<Vec<u32>> = Ok(vec![1, 2]);
+        assert_eq!(
+            envelope("capacity", &ok),
+            json!({ "command": "capacity", "ok": true, "result": [1, 2] })
+        );
+
+        let failed: Result<Vec<u32>> =
+            Err(anyhow!("No signature found")).with_code(ErrorCode::Verification);
+        let value = envelope("verify", &failed);
+        assert_eq!(value["ok"], false);
+        assert_eq!(value["error"]["code"], "verification");
+        assert_eq!(value["error"]["message"], "No signature found");
+    }
+
+    #[derive(Serialize)]
+    struct Count {
+        count: usize,
+    }
+
+    impl Display for Count {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            writeln!(f, "Counted {}", self.count)
+        }
+    }
+
+    impl Report for Count {
+        fn quiet(&self) -> Option<String> {
+            Some(self.count.to_string())
+        }
+    }
+
+    #[test]
+    fn test_rendered() {
+        let rendered = Rendered::new(&Count { count: 3 }).unwrap();
+        assert_eq!(rendered.to_string(), "Counted 3\n");
+        assert_eq!(rendered.quiet().as_deref(), Some("3"));
+        assert_eq!(rendered.raw(), None);
+        assert_eq!(
+            envelope("count", &Ok(rendered)),
+            json!({ "command": "count", "ok": true, "result": { "count": 3 } })
+        );
+    }
+
+    #[test]
+    fn test_format_in() {
+        let args = |line: &str| -> Vec<String> { line.split(' ').map(String::from).collect() };
+        assert_eq!(
+            OutputArgs::format_in(&args("pnglee list --bogus")),
+            Format::Text
+        );
+        assert_eq!(
+            OutputArgs::format_in(&args("pnglee --format json list")),
+            Format::Json
+        );
+        assert_eq!(
+            OutputArgs::format_in(&args("pnglee list --format=ndjson --bogus")),
+            Format::Ndjson
+        );
+        assert_eq!(
+            OutputArgs::format_in(&args("pnglee --format xml list")),
+            Format::Text
+        );
+        assert_eq!(
+            OutputArgs::format_in(&args("pnglee list -- --format json")),
+            Format::Text
+        );
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let codes = [
+            ErrorCode::Usage,
+            ErrorCode::NotFound,
+            ErrorCode::Io,
+            ErrorCode::InvalidPng,
+            ErrorCode::InvalidPayload,
+            ErrorCode::InvalidKey,
+            ErrorCode::Decryption,
+            ErrorCode::Verification,
+            ErrorCode::Capacity,
+            ErrorCode::Other,
+        ];
+        let mut exit_codes: Vec<u8> = codes.iter().map(|c| c.exit_code()).collect();
+        exit_codes.sort();
+        exit_codes.dedup();
+        assert_eq!(exit_codes.len(), codes.len());
+        assert!(!exit_codes.contains(&0));
+    }
+}
@@ -0,0 +1,429 @@
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+// every structure pnglee writes into a chunk starts with this
+pub const MAGIC_PREFIX: [u8; 3] = *b"pLe";
+
+// hidden data plus optional metadata about where it came from. Payloads
+// without metadata are stored as the raw bytes, which is also how older
+// versions stored plain messages
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Payload {
+    pub name: Option<String>,
+    pub mime: Option<String>,
+    pub data: Vec<u8>,
+    // deflate the data when stored
+    pub compressed: bool,
+}
+
+impl Payload {
+    // header layout: magic, version, flags, u16 name length, name,
+    // u16 mime length, mime, then the data itself
+    pub const MAGIC: [u8; 4] = *b"pLee";
+    pub const VERSION: u8 = 1;
+    const FIXED_HEADER_LENGTH: usize = 6;
+    const FLAG_DEFLATE: u8 = 0x01;
+    // refuse to inflate past this, so a tiny chunk cannot expand into
+    // gigabytes on decode
+    pub const MAX_DECOMPRESSED_LENGTH: usize = 64 * 1024 * 1024;
+
+    pub fn new(data: Vec<u8>) -> Payload {
+        Payload {
+            data,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Payload {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn with_mime(mut self, mime: &str) -> Payload {
+        self.mime = Some(mime.to_string());
+        self
+    }
+
+    pub fn with_compression(mut self) -> Payload {
+        self.compressed = true;
+        self
+    }
+
+    // raw data that looks like any pnglee structure still needs a
+    // header, or it would be misread on decode
+    fn needs_header(&self) -> bool {
+        self.name.is_some()
+            || self.mime.is_some()
+            || self.compressed
+            || self.data.starts_with(&MAGIC_PREFIX)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if !self.needs_header() {
+            return Ok(self.data.clone());
+        }
+
+        let mut bytes = Payload::MAGIC.to_vec();
+        bytes.push(Payload::VERSION);
+        bytes.push(if self.compressed {
+            Payload::FLAG_DEFLATE
+        } else {
+            0
+        });
+        for field in [&self.name, &self.mime] {
+            let field = field.as_deref().unwrap_or_default();
+            let length = u16::try_from(field.len())
+                .map_err(|_| anyhow!("Payload metadata field is too long"))?;
+            bytes.extend_from_slice(&length.to_be_bytes());
+            bytes.extend_from_slice(field.as_bytes());
+        }
+        if self.compressed {
+            let mut encoder = DeflateEncoder::new(bytes, Compression::best());
+            encoder.write_all(&self.data)?;
+            bytes = encoder.finish()?;
+        } else {
+            bytes.extend_from_slice(&self.data);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Payload> {
+        Payload::from_bytes_with_limit(bytes, Payload::MAX_DECOMPRESSED_LENGTH)
+    }
+
+    pub fn from_bytes_with_limit(bytes: &[u8], max_length: usize) -> Result<Payload> {
+        if !bytes.starts_with(&Payload::MAGIC) {
+            return Ok(Payload::new(bytes.to_vec()));
+        }
+        if bytes.len() < Payload::FIXED_HEADER_LENGTH {
+            return Err(anyhow!("Payload header is truncated"));
+        }
+
+        let version = bytes[4];
+        if version != Payload::VERSION {
+            return Err(anyhow!("Unsupported payload version {}", version));
+        }
+        let flags = bytes[5];
+        if flags & !Payload::FLAG_DEFLATE != 0 {
+            return Err(anyhow!("Unsupported payload flags {:#04x}", flags));
+        }
+        let compressed = flags & Payload::FLAG_DEFLATE != 0;
+
+        let mut rest = &bytes[Payload::FIXED_HEADER_LENGTH..];
+        let name = Payload::read_field(&mut rest)?;
+        let mime = Payload::read_field(&mut rest)?;
+
+        let data = if compressed {
+            Payload::inflate(rest, max_length)?
+        } else {
+            rest.to_vec()
+        };
+
+        Ok(Payload {
+            name,
+            mime,
+            data,
+            compressed,
+        })
+    }
+
+    // reads one byte past the limit to tell a payload that is exactly
+    // max_length long from one that is larger
+    fn inflate(compressed: &[u8], max_length: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        DeflateDecoder::new(compressed)
+            .take(max_length as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| anyhow!("Could not decompress payload: {}", e))?;
+        if data.len() > max_length {
+            return Err(anyhow!(
+                "Decompressed payload is larger than the {} byte limit",
+                max_length
+            ));
+        }
+        Ok(data)
+    }
+
+    // reads a length-prefixed string, advancing past it
+    fn read_field(rest: &mut &[u8]) -> Result<Option<String>> {
+        if rest.len() < 2 {
+            return Err(anyhow!("Payload header is truncated"));
+        }
+        let length = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        if rest.len() < 2 + length {
+            return Err(anyhow!("Payload header is truncated"));
+        }
+
+        let field = String::from_utf8(rest[2..2 + length].to_vec())?;
+        *rest = &rest[2 + length..];
+        Ok(if field.is_empty() { None } else { Some(field) })
+    }
+}
+
+// large payloads are spread over several chunks, each starting with
+// the part magic, a u32 sequence number and the u32 total part count
+pub const PART_MAGIC: [u8; 4] = *b"pLeS";
+const PART_HEADER_LENGTH: usize = 12;
+const MAX_LISTED_MISSING: u32 = 16;
+
+pub fn is_part(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PART_MAGIC)
+}
+
+pub fn split_into_parts(bytes: &[u8], part_size: usize) -> Result<Vec<Vec<u8>>> {
+    if part_size == 0 {
+        return Err(anyhow!("Part size must be greater than zero"));
+    }
+
+    let slices: Vec<&[u8]> = bytes.chunks(part_size).collect();
+    let total = u32::try_from(slices.len().max(1))
+        .map_err(|_| anyhow!("Payload would need too many parts"))?;
+
+    let parts = (0..total)
+        .map(|index| {
+            let mut part = PART_MAGIC.to_vec();
+            part.extend_from_slice(&index.to_be_bytes());
+            part.extend_from_slice(&total.to_be_bytes());
+            part.extend_from_slice(slices.get(index as usize).copied().unwrap_or_default());
+            part
+        })
+        .collect();
+    Ok(parts)
+}
+
+// parts may be in any order. A single chunk that is not a part is
+// returned as is, so unsplit payloads decode the same way
+pub fn join_parts(parts: &[&[u8]]) -> Result<Vec<u8>> {
+    match parts {
+        [] => return Err(anyhow!("No payload parts to join")),
+        [single] if !is_part(single) => return Ok(single.to_vec()),
+        _ => {}
+    }
+
+    let mut indexed: Vec<(u32, u32, &[u8])> = parts
+        .iter()
+        .map(|part| {
+            if !is_part(part) || part.len() < PART_HEADER_LENGTH {
+                return Err(anyhow!("Chunk is not a payload part"));
+            }
+            let index = u32::from_be_bytes(part[4..8].try_into().unwrap());
+            let total = u32::from_be_bytes(part[8..12].try_into().unwrap());
+            Ok((index, total, &part[PART_HEADER_LENGTH..]))
+        })
+        .collect::<Result<_>>()?;
+    indexed.sort_by_key(|(index, _, _)| *index);
+
+    let total = indexed[0].1;
+    if indexed.iter().any(|(_, t, _)| *t != total) {
+        return Err(anyhow!("Payload parts disagree on the total part count"));
+    }
+    if let Some(window) = indexed.windows(2).find(|w| w[0].0 == w[1].0) {
+        return Err(anyhow!("Payload part {} is duplicated", window[0].0));
+    }
+
+    if let Some((index, _, _)) = indexed.last().filter(|(index, _, _)| *index >= total) {
+        return Err(anyhow!(
+            "Payload part {} is beyond the total of {}",
+            index,
+            total
+        ));
+    }
+
+    // the parts are now unique and below the total, so any shortfall is
+    // missing parts. Those are only listed when there are few of them, as
+    // a crafted total can be as large as u32::MAX
+    let found = indexed.len() as u32;
+    if found != total {
+        if total - found > MAX_LISTED_MISSING {
+            return Err(anyhow!("Payload has {} of {} parts", found, total));
+        }
+        let missing: Vec<String> = (0..total)
+            .filter(|i| {
+                indexed
+                    .binary_search_by_key(i, |(index, _, _)| *index)
+                    .is_err()
+            })
+            .map(|i| i.to_string())
+            .collect();
+        return Err(anyhow!(
+            "Payload parts missing: {} of {}",
+            missing.join(", "),
+            total
+        ));
+    }
+
+    Ok(indexed
+        .into_iter()
+        .flat_map(|(_, _, data)| data.iter().copied())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_payload_is_raw() {
+        let payload = Payload::new(b"hello".to_vec());
+        assert_eq!(payload.to_bytes().unwrap(), b"hello");
+        assert_eq!(Payload::from_bytes(b"hello").unwrap(), payload);
+    }
+
+    #[test]
+    fn test_payload_with_metadata_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let payload = Payload::new(data)
+            .with_name("payload.bin")
+            .with_mime("application/octet-stream");
+
+        let bytes = payload.to_bytes().unwrap();
+        assert!(bytes.starts_with(&Payload::MAGIC));
+        assert_eq!(Payload::from_bytes(&bytes).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_payload_starting_with_magic() {
+        let payload = Payload::new(b"pLee is not a header".to_vec());
+        let bytes = payload.to_bytes().unwrap();
+        assert_eq!(Payload::from_bytes(&bytes).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_payload_starting_with_prefix() {
+        let payload = Payload::new(b"pLeE looks encrypted".to_vec());
+        let bytes = payload.to_bytes().unwrap();
+        assert!(bytes.starts_with(&Payload::MAGIC));
+        assert_eq!(Payload::from_bytes(&bytes).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let payload = Payload::new(vec![1, 2, 3]).with_name("a-long-file-name.bin");
+        let bytes = payload.to_bytes().unwrap();
+        assert!(Payload::from_bytes(&bytes[..10]).is_err());
+        assert!(Payload::from_bytes(&bytes[..5]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_version() {
+        let mut bytes = Payload::new(vec![1])
+            .with_mime("text/plain")
+            .to_bytes()
+            .unwrap();
+        bytes[4] = 9;
+        assert!(Payload::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let json = br#"{"id": 1, "tags": ["a", "b"]}"#.repeat(100);
+        let payload = Payload::new(json.clone())
+            .with_mime("application/json")
+            .with_compression();
+
+        let bytes = payload.to_bytes().unwrap();
+        assert!(bytes.len() < json.len() / 10);
+        assert_eq!(Payload::from_bytes(&bytes).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let payload = Payload::new(vec![0; 10_000]).with_compression();
+        let bytes = payload.to_bytes().unwrap();
+
+        assert!(Payload::from_bytes_with_limit(&bytes, 10_000).is_ok());
+        let err = Payload::from_bytes_with_limit(&bytes, 9_999).unwrap_err();
+        assert!(err.to_string().contains("byte limit"));
+    }
+
+    #[test]
+    fn test_corrupt_compressed_data() {
+        let mut bytes = Payload::new(b"hello".repeat(50))
+            .with_compression()
+            .to_bytes()
+            .unwrap();
+        bytes.truncate(bytes.len() - 3);
+        assert!(Payload::from_bytes(&bytes).is_err());
+    }
+
+    fn parts_of(data: &[u8], part_size: usize) -> Vec<Vec<u8>> {
+        split_into_parts(data, part_size).unwrap()
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut parts = parts_of(&data, 30);
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|p| is_part(p)));
+
+        parts.reverse();
+        let refs: Vec<&[u8]> = parts.iter().map(|p| p.as_slice()).collect();
+        assert_eq!(join_parts(&refs).unwrap(), data);
+    }
+
+    #[test]
+    fn test_split_empty_payload() {
+        let parts = parts_of(&[], 30);
+        assert_eq!(parts.len(), 1);
+
+        let refs: Vec<&[u8]> = parts.iter().map(|p| p.as_slice()).collect();
+        assert!(join_parts(&refs).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_join_single_unsplit_chunk() {
+        assert_eq!(join_parts(&[b"plain message"]).unwrap(), b"plain message");
+    }
+
+    #[test]
+    fn test_join_missing_part() {
+        let parts = parts_of(&[7; 100], 10);
+        let refs: Vec<&[u8]> = parts
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 3 && *i != 7)
+            .map(|(_, p)| p.as_slice())
+            .collect();
+
+        let err = join_parts(&refs).unwrap_err();
+        assert_eq!(err.to_string(), "Payload parts missing: 3, 7 of 10");
+    }
+
+    #[test]
+    fn test_join_huge_total() {
+        let mut parts = parts_of(&[7; 20], 10);
+        for part in &mut parts {
+            part[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        }
+        let refs: Vec<&[u8]> = parts.iter().map(|p| p.as_slice()).collect();
+
+        let err = join_parts(&refs).unwrap_err();
+        assert_eq!(err.to_string(), "Payload has 2 of 4294967295 parts");
+    }
+
+    #[test]
+    fn test_join_duplicated_part() {
+        let parts = parts_of(&[7; 30], 10);
+        let refs: Vec<&[u8]> = vec![&parts[0], &parts[1], &parts[1], &parts[2]];
+
+        let err = join_parts(&refs).unwrap_err();
+        assert_eq!(err.to_string(), "Payload part 1 is duplicated");
+    }
+
+    #[test]
+    fn test_join_mixed_parts() {
+        let first = parts_of(&[1; 30], 10);
+        let second = parts_of(&[2; 40], 10);
+        let refs: Vec<&[u8]> = vec![&first[0], &first[1], &second[2]];
+
+        assert!(join_parts(&refs).is_err());
+        assert!(join_parts(&[&first[0], b"not a part"]).is_err());
+    }
+}
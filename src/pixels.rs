@@ -0,0 +1,268 @@
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::known_chunk::Ihdr;
+use crate::png::Png;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+// limit on the filtered scanlines of one image, 1 GiB
+const MAX_DECODED_BYTES: usize = 1 << 30;
+
+// decoded, unfiltered scanlines of a non-interlaced image. Only 8 and 16
+// bit direct colour images are supported, as changing the low bits of
+// palette indices or packed samples would visibly change the image
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub header: Ihdr,
+    // scanlines back to back, without their filter type bytes
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_png(png: &Png) -> Result<Image> {
+        let ihdr = png
+            .chunk_by_type("IHDR")
+            .ok_or_else(|| anyhow!("Image has no IHDR chunk"))?;
+        let header = Ihdr::try_from(ihdr.data())?;
+        Image::check_supported(&header)?;
+
+        let compressed: Vec<u8> = png
+            .image_data_chunks()
+            .iter()
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+
+        let stride = Image::stride(&header);
+        let expected = (stride + 1)
+            .checked_mul(header.height as usize)
+            .filter(|&length| length <= MAX_DECODED_BYTES)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Image of {}x{} is too large to decode",
+                    header.width,
+                    header.height
+                )
+            })?;
+        // grown as the stream inflates, as the header may promise far more
+        // data than the file holds
+        let mut filtered = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .take(expected as u64)
+            .read_to_end(&mut filtered)
+            .map_err(|e| anyhow!("Could not decompress image data: {}", e))?;
+        if filtered.len() < expected {
+            return Err(anyhow!("Image data is truncated"));
+        }
+
+        let data = unfilter(&filtered, stride, Image::bytes_per_pixel(&header))?;
+        Ok(Image { header, data })
+    }
+
+    // replaces the IDAT chunks with the current pixel data
+    pub fn write_to(&self, png: &mut Png) -> Result<()> {
+        let filtered = filter(
+            &self.data,
+            Image::stride(&self.header),
+            Image::bytes_per_pixel(&self.header),
+        );
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&filtered)?;
+        png.replace_image_data(encoder.finish()?)
+    }
+
+    fn check_supported(header: &Ihdr) -> Result<()> {
+        if header.interlace_method != 0 {
+            return Err(anyhow!("Interlaced images are not supported"));
+        }
+        if header.color_type == 3 {
+            return Err(anyhow!("Palette images are not supported"));
+        }
+        if header.bit_depth != 8 && header.bit_depth != 16 {
+            return Err(anyhow!(
+                "Bit depth {} is not supported, only 8 and 16",
+                header.bit_depth
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn channels(&self) -> usize {
+        channel_count(self.header.color_type)
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        self.header.width as usize * self.header.height as usize
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        self.header.bit_depth as usize / 8
+    }
+
+    fn bytes_per_pixel(header: &Ihdr) -> usize {
+        channel_count(header.color_type) * header.bit_depth as usize / 8
+    }
+
+    fn stride(header: &Ihdr) -> usize {
+        header.width as usize * Image::bytes_per_pixel(header)
+    }
+}
+
+pub fn channel_count(color_type: u8) -> usize {
+    match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        _ => 4,
+    }
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let p = left as i16 + up as i16 - up_left as i16;
+    let pa = (p - left as i16).abs();
+    let pb = (p - up as i16).abs();
+    let pc = (p - up_left as i16).abs();
+    if pa <= pb && pa <= pc {
+        left
+    } else if pb <= pc {
+        up
+    } else {
+        up_left
+    }
+}
+
+// the byte each filter type predicts from its neighbours
+fn predict(filter_type: u8, line: &[u8], previous: &[u8], i: usize, bpp: usize) -> u8 {
+    let left = if i >= bpp { line[i - bpp] } else { 0 };
+    let up = previous[i];
+    let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+    match filter_type {
+        1 => left,
+        2 => up,
+        3 => ((left as u16 + up as u16) / 2) as u8,
+        4 => paeth(left, up, up_left),
+        _ => 0,
+    }
+}
+
+fn unfilter(filtered: &[u8], stride: usize, bpp: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(filtered.len());
+    let mut previous = vec![0u8; stride];
+
+    for (row, line) in filtered.chunks_exact(stride + 1).enumerate() {
+        let filter_type = line[0];
+        if filter_type > 4 {
+            return Err(anyhow!(
+                "Unknown filter type {} on row {}",
+                filter_type,
+                row
+            ));
+        }
+
+        let mut current = line[1..].to_vec();
+        for i in 0..stride {
+            let predicted = predict(filter_type, &current, &previous, i, bpp);
+            current[i] = current[i].wrapping_add(predicted);
+        }
+        data.extend_from_slice(&current);
+        previous = current;
+    }
+    Ok(data)
+}
+
+// picks the filter with the smallest sum of absolute differences for
+// each row, the usual heuristic for keeping the output small
+fn filter(data: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+    let mut filtered = Vec::with_capacity(data.len() + data.len() / stride.max(1));
+    let empty = vec![0u8; stride];
+    let mut previous: &[u8] = &empty;
+
+    for line in data.chunks_exact(stride) {
+        let best = (0..=4u8)
+            .map(|filter_type| {
+                let row: Vec<u8> = (0..stride)
+                    .map(|i| line[i].wrapping_sub(predict(filter_type, line, previous, i, bpp)))
+                    .collect();
+                let cost: u64 = row.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum();
+                (cost, filter_type, row)
+            })
+            .min_by_key(|(cost, _, _)| *cost)
+            .unwrap();
+
+        filtered.push(best.1);
+        filtered.extend(best.2);
+        previous = line;
+    }
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_FILE: &[u8] = include_bytes!("../capy.png");
+
+    #[test]
+    fn test_decode_capy() {
+        let png = Png::try_from(PNG_FILE).unwrap();
+        let image = Image::from_png(&png).unwrap();
+
+        assert_eq!(image.channels(), 4);
+        assert_eq!(image.data.len(), image.pixel_count() * 4);
+    }
+
+    #[test]
+    fn test_reencode_keeps_pixels() {
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        let image = Image::from_png(&png).unwrap();
+
+        image.write_to(&mut png).unwrap();
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(Image::from_png(&reparsed).unwrap().data, image.data);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let data: Vec<u8> = (0..600u32).map(|i| (i * 7 % 251) as u8).collect();
+        let filtered = filter(&data, 30, 3);
+        assert_eq!(unfilter(&filtered, 30, 3).unwrap(), data);
+    }
+
+    #[test]
+    fn test_oversized_header() {
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        let mut ihdr = png.chunk_data_mut(0).unwrap();
+        ihdr[0..4].copy_from_slice(&2_000_000_000u32.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&2_000_000_000u32.to_be_bytes());
+        ihdr[8] = 16;
+        ihdr.finish().unwrap();
+
+        let err = Image::from_png(&png).unwrap_err();
+        assert!(err.to_string().contains("too large"));
+
+        // within the limit, but far more than the IDAT data holds
+        let mut ihdr = png.chunk_data_mut(0).unwrap();
+        ihdr[0..4].copy_from_slice(&8000u32.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&8000u32.to_be_bytes());
+        ihdr.finish().unwrap();
+        let err = Image::from_png(&png).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+    }
+
+    #[test]
+    fn test_unsupported_bit_depth() {
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        let mut ihdr = png.chunk_data_mut(0).unwrap();
+        ihdr[8] = 4;
+        ihdr[9] = 0;
+        ihdr.finish().unwrap();
+
+        let err = Image::from_png(&png).unwrap_err();
+        assert!(err.to_string().contains("Bit depth 4"));
+    }
+}
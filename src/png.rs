@@ -1,34 +1,500 @@
 use core::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 
 use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::codec::{self, ChunkCodec, CodecRegistry};
+use crate::known_chunk::{KnownChunk, KnownType};
 
 use anyhow::anyhow;
 use anyhow::Result;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseMode {
+    Strict,
+    // problems are recorded as diagnostics rather than failing the parse
+    Lenient,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    CrcMismatch {
+        index: usize,
+        chunk_type: String,
+        stored: u32,
+        computed: u32,
+    },
+    // bytes that could not be parsed as a chunk, from offset to end of file
+    TrailingGarbage {
+        offset: usize,
+        length: usize,
+    },
+    MissingIend,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::CrcMismatch {
+                index,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Chunk {} ({}) has CRC {:08x} but data gives {:08x}",
+                index, chunk_type, stored, computed
+            ),
+            Diagnostic::TrailingGarbage { offset, length } => {
+                write!(f, "{} unparseable bytes at offset {}", length, offset)
+            }
+            Diagnostic::MissingIend => write!(f, "No IEND chunk"),
+        }
+    }
+}
+
+// where to insert chunks, relative to the image structure or by index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    AfterIhdr,
+    BeforeIdat,
+    AfterIdat,
+    BeforeIend,
+    Index(usize),
+}
+
+impl FromStr for Position {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Position> {
+        match s {
+            "after-ihdr" => Ok(Position::AfterIhdr),
+            "before-idat" => Ok(Position::BeforeIdat),
+            "after-idat" => Ok(Position::AfterIdat),
+            "before-iend" => Ok(Position::BeforeIend),
+            _ => s.parse().map(Position::Index).map_err(|_| {
+                anyhow!(
+                    "Position must be after-ihdr, before-idat, after-idat, before-iend or an index, got {}",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+// where the spec lets an ancillary chunk go relative to PLTE and IDAT
+enum Placement {
+    BeforePlte,
+    BeforeIdat,
+    Anywhere,
+}
+
+impl Placement {
+    fn of(chunk_type: &str) -> Placement {
+        match chunk_type {
+            "cHRM" | "gAMA" | "iCCP" | "sBIT" | "sRGB" | "cICP" | "mDCv" | "cLLI" => {
+                Placement::BeforePlte
+            }
+            "bKGD" | "hIST" | "tRNS" | "pHYs" | "sPLT" | "eXIf" | "acTL" | "oFFs" | "pCAL"
+            | "sCAL" => Placement::BeforeIdat,
+            _ => Placement::Anywhere,
+        }
+    }
+}
+
+// ancillary chunks the spec allows at most one of
+const UNIQUE_ANCILLARY: [&str; 15] = [
+    "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCv", "cLLI", "bKGD", "hIST", "tRNS", "pHYs",
+    "eXIf", "tIME", "acTL",
+];
+
 pub struct Png {
     chunks: Vec<Chunk>,
+    codecs: CodecRegistry,
+    keep_unsafe: bool,
+    discarded: Vec<Chunk>,
+    diagnostics: Vec<Diagnostic>,
+    trailing_data: Vec<u8>,
 }
 
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
-    pub fn extract_chunk_from_bytes(start: usize, bytes: &[u8]) -> (Chunk, usize) {
+    pub fn extract_chunk_from_bytes(
+        start: usize,
+        bytes: &[u8],
+        mode: ParseMode,
+    ) -> Result<(Chunk, usize)> {
         let n_extra_bytes = 12;
-        let total_length = Chunk::chunk_data_length(&bytes[start..]) + n_extra_bytes;
-        let end = start as usize + total_length as usize;
+        let truncated = || anyhow!("Truncated chunk at offset {}", start);
+        let rest = bytes
+            .get(start..)
+            .filter(|rest| rest.len() >= n_extra_bytes)
+            .ok_or_else(truncated)?;
+
+        let total_length = Chunk::chunk_data_length(rest) as usize + n_extra_bytes;
+        let data_bytes = rest.get(..total_length).ok_or_else(truncated)?;
+        let chunk = match mode {
+            ParseMode::Strict => Chunk::try_from(data_bytes)?,
+            ParseMode::Lenient => Chunk::try_from_lenient(data_bytes)?,
+        };
+        Ok((chunk, start + total_length))
+    }
 
-        let data_bytes = &bytes[start..end];
-        (Chunk::try_from(data_bytes).unwrap(), end)
+    // serialized records back to back, without a PNG signature. Every
+    // CRC must match
+    pub fn parse_chunk_records(bytes: &[u8]) -> Result<Vec<Chunk>> {
+        let mut start = 0;
+        let mut chunks = vec![];
+        while start < bytes.len() {
+            let (chunk, end) = Png::extract_chunk_from_bytes(start, bytes, ParseMode::Strict)
+                .map_err(|e| anyhow!("Chunk record {} at offset {}: {}", chunks.len(), start, e))?;
+            chunks.push(chunk);
+            start = end;
+        }
+        if chunks.is_empty() {
+            return Err(anyhow!("No chunk records found"));
+        }
+        Ok(chunks)
+    }
+
+    pub fn parse(bytes: &[u8], mode: ParseMode) -> Result<Png> {
+        if bytes.len() < 8 || bytes[0..8] != Png::STANDARD_HEADER {
+            return Err(anyhow!("Header bytes invalid"));
+        }
+
+        let mut start = 8;
+        let mut chunks: Vec<Chunk> = vec![];
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let mut trailing_data: Vec<u8> = vec![];
+
+        while start < bytes.len() {
+            let (sub_chunk, end) = match Png::extract_chunk_from_bytes(start, bytes, mode) {
+                Ok(extracted) => extracted,
+                Err(e) => match mode {
+                    ParseMode::Strict => return Err(e),
+                    ParseMode::Lenient => {
+                        diagnostics.push(Diagnostic::TrailingGarbage {
+                            offset: start,
+                            length: bytes.len() - start,
+                        });
+                        break;
+                    }
+                },
+            };
+
+            if !sub_chunk.is_crc_valid() {
+                diagnostics.push(Diagnostic::CrcMismatch {
+                    index: chunks.len(),
+                    chunk_type: sub_chunk.chunk_type().to_string(),
+                    stored: sub_chunk.stored_crc(),
+                    computed: sub_chunk.computed_crc(),
+                });
+            }
+            let is_iend = sub_chunk.known_type() == Some(KnownType::Iend);
+            chunks.push(sub_chunk);
+            start = end;
+
+            // nothing may follow IEND, so anything that does is kept
+            // aside rather than parsed as chunks
+            if is_iend {
+                trailing_data = bytes[start..].to_vec();
+                break;
+            }
+        }
+
+        let has_iend = chunks
+            .iter()
+            .any(|c| c.known_type() == Some(KnownType::Iend));
+        if mode == ParseMode::Lenient && !has_iend {
+            diagnostics.push(Diagnostic::MissingIend);
+        }
+
+        let mut png = Png::from_chunks(chunks);
+        png.diagnostics = diagnostics;
+        png.trailing_data = trailing_data;
+        Ok(png)
+    }
+
+    // bytes found after IEND, written back out by as_bytes
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing_data
+    }
+
+    pub fn set_trailing_data(&mut self, data: Vec<u8>) {
+        self.trailing_data = data;
+    }
+
+    pub fn strip_trailing_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailing_data)
+    }
+
+    // problems found by a lenient parse, empty for strict parses
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    // recomputes every CRC and restores a missing IEND, returning the
+    // problems that were fixed. Unparseable garbage is never kept by the
+    // parser, but data after IEND is, and is left for the caller to strip
+    pub fn repair(&mut self) -> Result<Vec<Diagnostic>> {
+        let mut fixed = std::mem::take(&mut self.diagnostics);
+
+        for chunk in &mut self.chunks {
+            chunk.refresh();
+        }
+
+        if self.chunk_type_to_first_index("IEND").is_none() {
+            self.chunks
+                .push(Chunk::new(ChunkType::try_from(*b"IEND")?, vec![])?);
+            if !fixed.contains(&Diagnostic::MissingIend) {
+                fixed.push(Diagnostic::MissingIend);
+            }
+        }
+
+        Ok(fixed)
     }
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Png { chunks }
+        Png {
+            chunks,
+            codecs: CodecRegistry::default(),
+            keep_unsafe: false,
+            discarded: vec![],
+            diagnostics: vec![],
+            trailing_data: vec![],
+        }
     }
 
+    // chunks are added before IEND, which must stay last
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.chunks.push(chunk);
+        let is_critical = chunk.chunk_type().is_critical();
+        match self.chunk_type_to_first_index("IEND") {
+            Some(index) => self.chunks.insert(index, chunk),
+            None => self.chunks.push(chunk),
+        }
+        if is_critical {
+            self.critical_data_changed();
+        }
+    }
+
+    // the index a chunk inserted at the position would end up at
+    pub fn position_index(&self, position: Position) -> Result<usize> {
+        let iend = self
+            .chunk_type_to_first_index("IEND")
+            .unwrap_or(self.chunks.len());
+        let idats = self.indices_of("IDAT");
+        match position {
+            Position::AfterIhdr => self
+                .chunk_type_to_first_index("IHDR")
+                .map(|index| index + 1)
+                .ok_or_else(|| anyhow!("No IHDR chunk to insert after")),
+            Position::BeforeIdat => idats
+                .first()
+                .copied()
+                .ok_or_else(|| anyhow!("No IDAT chunk to insert before")),
+            Position::AfterIdat => idats
+                .last()
+                .map(|index| index + 1)
+                .ok_or_else(|| anyhow!("No IDAT chunk to insert after")),
+            Position::BeforeIend => Ok(iend),
+            // nothing may come before IHDR, so the first index allowed is after it
+            Position::Index(index) => {
+                let first = self
+                    .chunk_type_to_first_index("IHDR")
+                    .map_or(0, |ihdr| ihdr + 1);
+                if index < first {
+                    Err(anyhow!(
+                        "Cannot insert at index {}, IHDR must stay the first chunk",
+                        index
+                    ))
+                } else if index > iend {
+                    Err(anyhow!(
+                        "Cannot insert at index {}, IEND is at {}",
+                        index,
+                        iend
+                    ))
+                } else {
+                    Ok(index)
+                }
+            }
+        }
+    }
+
+    // IHDR and IEND can't be inserted, as there must be exactly one of each
+    pub fn insert_chunk(&mut self, position: Position, chunk: Chunk) -> Result<usize> {
+        let chunk_type = chunk.chunk_type().to_string();
+        if chunk_type == "IHDR" || chunk_type == "IEND" {
+            return Err(anyhow!("Cannot insert another {} chunk", chunk_type));
+        }
+
+        let index = self.position_index(position)?;
+        let is_critical = chunk.chunk_type().is_critical();
+        self.chunks.insert(index, chunk);
+        if is_critical {
+            self.critical_data_changed();
+        }
+        Ok(index)
+    }
+
+    // copies the source's ancillary chunks that pass the filter, in source
+    // order. Each goes where the spec requires, and chunks with no rule
+    // stay on the same side of the image data as in the source. A copied
+    // chunk the spec allows only one of replaces any existing one, and
+    // chunks already present byte for byte are skipped so copying twice
+    // changes nothing. Returns the types copied
+    pub fn copy_ancillary_from<F: Fn(&Chunk) -> bool>(
+        &mut self,
+        source: &Png,
+        filter: F,
+    ) -> Vec<String> {
+        let source_idat = source.chunk_type_to_first_index("IDAT");
+        let mut copied = vec![];
+
+        for (index, chunk) in source.chunks.iter().enumerate() {
+            if chunk.chunk_type().is_critical() || !filter(chunk) {
+                continue;
+            }
+            let already_present = self
+                .chunks
+                .iter()
+                .any(|c| c.chunk_type() == chunk.chunk_type() && c.data() == chunk.data());
+            if already_present {
+                continue;
+            }
+            let chunk_type = chunk.chunk_type().to_string();
+            if UNIQUE_ANCILLARY.contains(&chunk_type.as_str()) {
+                self.chunks
+                    .retain(|c| c.chunk_type().to_string() != chunk_type);
+            }
+
+            let before_image = source_idat.is_some_and(|idat| index < idat);
+            let position = match Placement::of(&chunk_type) {
+                Placement::BeforePlte => self
+                    .chunk_type_to_first_index("PLTE")
+                    .or_else(|| self.chunk_type_to_first_index("IDAT")),
+                Placement::BeforeIdat => self.chunk_type_to_first_index("IDAT"),
+                Placement::Anywhere if before_image => self.chunk_type_to_first_index("IDAT"),
+                Placement::Anywhere => None,
+            }
+            .or_else(|| self.chunk_type_to_first_index("IEND"))
+            .unwrap_or(self.chunks.len());
+
+            self.chunks.insert(position, chunk.clone());
+            copied.push(chunk_type);
+        }
+        copied
+    }
+
+    // overrides the spec's rule so unknown unsafe-to-copy chunks
+    // survive edits to critical chunks
+    pub fn set_keep_unsafe(&mut self, keep_unsafe: bool) {
+        self.keep_unsafe = keep_unsafe;
+    }
+
+    // chunks dropped by edits to critical data, in the order dropped
+    pub fn discarded_chunks(&self) -> &[Chunk] {
+        &self.discarded
+    }
+
+    // an unknown chunk is one neither the spec nor a codec defines, so we
+    // cannot tell whether it still agrees with the critical data
+    fn is_unknown_unsafe_to_copy(&self, chunk_type: &ChunkType) -> bool {
+        let type_string = chunk_type.to_string();
+        !chunk_type.is_critical()
+            && !chunk_type.is_safe_to_copy()
+            && !KnownChunk::is_registered(&type_string)
+            && !self.codecs.is_registered(&type_string)
+    }
+
+    // spec: unknown unsafe-to-copy chunks must not be copied to a file
+    // whose critical chunks have been modified
+    fn critical_data_changed(&mut self) {
+        if self.keep_unsafe {
+            return;
+        }
+
+        let (discarded, kept): (Vec<Chunk>, Vec<Chunk>) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|c| self.is_unknown_unsafe_to_copy(c.chunk_type()));
+        self.chunks = kept;
+        self.discarded.extend(discarded);
+    }
+
+    // the IDAT chunks in order, whose data joined is the compressed image
+    pub fn image_data_chunks(&self) -> Vec<&Chunk> {
+        self.chunks
+            .iter()
+            .filter(|c| c.known_type() == Some(KnownType::Idat))
+            .collect()
+    }
+
+    // replaces all IDAT chunks with a single one at the position of
+    // the first, or before IEND if there was none
+    pub fn replace_image_data(&mut self, data: Vec<u8>) -> Result<()> {
+        let idat = Chunk::new(ChunkType::try_from(*b"IDAT")?, data)?;
+        let position = self
+            .chunk_type_to_first_index("IDAT")
+            .or_else(|| self.chunk_type_to_first_index("IEND"))
+            .unwrap_or(self.chunks.len());
+
+        self.chunks
+            .retain(|c| c.known_type() != Some(KnownType::Idat));
+        self.chunks.insert(position, idat);
+        self.critical_data_changed();
+        Ok(())
+    }
+
+    // existing chunks of the codec's type are validated on registration
+    pub fn register_codec<C: ChunkCodec + 'static>(&mut self, codec: C) -> Result<()> {
+        let mut codecs = self.codecs.clone();
+        codecs.register(codec)?;
+        self.use_codecs(codecs)
+    }
+
+    pub fn use_codecs(&mut self, codecs: CodecRegistry) -> Result<()> {
+        for chunk in &self.chunks {
+            codecs.validate(chunk)?;
+        }
+        self.codecs = codecs;
+        Ok(())
+    }
+
+    pub fn codecs(&self) -> &CodecRegistry {
+        &self.codecs
+    }
+
+    // checks every chunk against its typed parser or registered codec
+    pub fn validate(&self) -> Result<()> {
+        for chunk in &self.chunks {
+            chunk.parse_known()?;
+            self.codecs.validate(chunk)?;
+        }
+        Ok(())
+    }
+
+    pub fn describe_chunk(&self, chunk: &Chunk) -> Result<String> {
+        match self.codecs.describe(chunk) {
+            Some(description) => description,
+            None => Ok(chunk.to_string()),
+        }
+    }
+
+    pub fn append_custom<C: ChunkCodec>(&mut self, codec: &C, value: &C::Value) -> Result<()> {
+        let chunk = codec::encode_chunk(codec, value)?;
+        self.append_chunk(chunk);
+        Ok(())
+    }
+
+    pub fn decode_custom<C: ChunkCodec>(&self, codec: &C) -> Result<Vec<C::Value>> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == codec.chunk_type())
+            .map(|c| codec.decode(c.data()))
+            .collect()
     }
 
     fn header(&self) -> &[u8; 8] {
@@ -54,22 +520,161 @@ impl Png {
 
     pub fn remove_first_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
         match self.chunk_type_to_first_index(chunk_type) {
-            Some(index) => return Ok(self.chunks.remove(index)),
-            None => return Err(anyhow!("No matching chunk for {:?}", chunk_type)),
+            Some(index) => {
+                let removed = self.chunks.remove(index);
+                if removed.chunk_type().is_critical() {
+                    self.critical_data_changed();
+                }
+                Ok(removed)
+            }
+            None => Err(anyhow!("No matching chunk for {:?}", chunk_type)),
         }
     }
 
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&Chunk> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == chunk_type)
+            .collect()
+    }
+
+    pub fn indices_of(&self, chunk_type: &str) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.chunk_type().to_string() == chunk_type)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn remove_all_chunks(&mut self, chunk_type: &str) -> Vec<Chunk> {
+        let (removed, kept): (Vec<Chunk>, Vec<Chunk>) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|c| c.chunk_type().to_string() == chunk_type);
+        self.chunks = kept;
+
+        if removed.iter().any(|c| c.chunk_type().is_critical()) {
+            self.critical_data_changed();
+        }
+        removed
+    }
+
+    // returns the chunk that was replaced
+    pub fn replace_chunk(&mut self, index: usize, chunk: Chunk) -> Result<Chunk> {
+        self.check_index(index)?;
+        let is_critical = chunk.chunk_type().is_critical();
+        let replaced = std::mem::replace(&mut self.chunks[index], chunk);
+
+        if is_critical || replaced.chunk_type().is_critical() {
+            self.critical_data_changed();
+        }
+        Ok(replaced)
+    }
+
+    // `to` is the index the chunk ends up at once moved
+    pub fn move_chunk(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check_index(from)?;
+        self.check_index(to)?;
+
+        let chunk = self.chunks.remove(from);
+        let is_critical = chunk.chunk_type().is_critical();
+        self.chunks.insert(to, chunk);
+
+        if is_critical && from != to {
+            self.critical_data_changed();
+        }
+        Ok(())
+    }
+
+    pub fn retain_chunks<F: FnMut(&Chunk) -> bool>(&mut self, mut keep: F) {
+        let mut removed_critical = false;
+        self.chunks.retain(|c| {
+            let retained = keep(c);
+            removed_critical |= !retained && c.chunk_type().is_critical();
+            retained
+        });
+
+        if removed_critical {
+            self.critical_data_changed();
+        }
+    }
+
+    // the edits only take effect once the guard's finish is called
+    pub fn chunk_data_mut(&mut self, index: usize) -> Result<ChunkDataMut<'_>> {
+        self.check_index(index)?;
+        let data = self.chunks[index].data().to_vec();
+        Ok(ChunkDataMut {
+            png: self,
+            index,
+            data,
+        })
+    }
+
+    fn check_index(&self, index: usize) -> Result<()> {
+        if index >= self.chunks.len() {
+            return Err(anyhow!(
+                "Chunk index {} out of range for {} chunks",
+                index,
+                self.chunks.len()
+            ));
+        }
+        Ok(())
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let data_bytes: Vec<u8> = self.chunks.iter().flat_map(|c| c.as_bytes()).collect();
 
-        Png::STANDARD_HEADER
+        self.header()
             .iter()
             .chain(data_bytes.iter())
+            .chain(self.trailing_data.iter())
             .cloned()
             .collect()
     }
 }
 
+// edits are made to a copy of the data, which finish writes back with
+// the length and CRC recomputed. Dropping the guard without calling
+// finish discards the edits
+#[must_use = "edits are discarded unless finish is called"]
+pub struct ChunkDataMut<'a> {
+    png: &'a mut Png,
+    index: usize,
+    data: Vec<u8>,
+}
+
+impl ChunkDataMut<'_> {
+    // fails if the data has grown beyond Chunk::MAX_DATA_LENGTH. Only a
+    // critical chunk whose bytes actually changed counts as a critical edit
+    pub fn finish(self) -> Result<()> {
+        let ChunkDataMut { png, index, data } = self;
+        let chunk = &mut png.chunks[index];
+        if chunk.data() == data.as_slice() {
+            return Ok(());
+        }
+
+        chunk.set_data(data)?;
+        if chunk.chunk_type().is_critical() {
+            png.critical_data_changed();
+        }
+        Ok(())
+    }
+}
+
+impl Deref for ChunkDataMut<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for ChunkDataMut<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
 impl Display for Png {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let joined: String = self.chunks.iter().map(|c| c.to_string()).collect();
@@ -81,22 +686,7 @@ impl TryFrom<&[u8]> for Png {
     type Error = anyhow::Error;
 
     fn try_from(bytes: &[u8]) -> Result<Self> {
-        let header_bytes = &bytes[0..8];
-        if header_bytes != &Png::STANDARD_HEADER {
-            return Err(anyhow!("Header bytes invalid"));
-        }
-
-        let remaining_bytes = &bytes[8..];
-        let mut start = 8;
-        let mut chunks: Vec<Chunk> = vec![];
-
-        while start <= remaining_bytes.len() {
-            let (sub_chunk, end) = Png::extract_chunk_from_bytes(start, bytes);
-            chunks.push(sub_chunk);
-            start = end;
-        }
-
-        return Ok(Png { chunks: chunks });
+        Png::parse(bytes, ParseMode::Strict)
     }
 }
 
@@ -105,6 +695,7 @@ mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
+    use crate::codec::tests::{BoundingBox, BoundingBoxCodec};
     use std::convert::TryFrom;
     use std::str::FromStr;
 
@@ -125,7 +716,7 @@ mod tests {
         let chunk_type = ChunkType::from_str(chunk_type)?;
         let data: Vec<u8> = data.bytes().collect();
 
-        Ok(Chunk::new(chunk_type, data))
+        Chunk::new(chunk_type, data)
     }
 
     #[test]
@@ -289,6 +880,438 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    #[test]
+    fn test_critical_edit_discards_unknown_unsafe() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("prVN", "provenance").unwrap());
+        png.append_chunk(chunk_from_strings("prVn", "safe provenance").unwrap());
+
+        png.remove_first_chunk("FrSt").unwrap();
+
+        assert!(png.chunk_by_type("prVN").is_none());
+        assert!(png.chunk_by_type("prVn").is_some());
+        assert_eq!(png.discarded_chunks().len(), 1);
+        assert_eq!(png.discarded_chunks()[0].chunk_type().to_string(), "prVN");
+    }
+
+    #[test]
+    fn test_critical_edit_keeps_registered_unsafe() {
+        let mut png = Png::try_from(&include_bytes!("../capy.png")[..]).unwrap();
+        png.append_chunk(chunk_from_strings("tRNS", "\0\x01").unwrap());
+        png.append_chunk(chunk_from_strings("prVN", "provenance").unwrap());
+
+        png.replace_image_data(vec![1, 2, 3]).unwrap();
+
+        for chunk_type in ["cHRM", "bKGD", "tRNS"] {
+            assert!(png.chunk_by_type(chunk_type).is_some(), "{}", chunk_type);
+        }
+        assert_eq!(png.discarded_chunks().len(), 1);
+        assert_eq!(png.discarded_chunks()[0].chunk_type().to_string(), "prVN");
+    }
+
+    #[test]
+    fn test_ancillary_edit_keeps_unsafe() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("prVN", "provenance").unwrap());
+
+        png.remove_first_chunk("miDl").unwrap();
+        png.append_chunk(chunk_from_strings("teSt", "message").unwrap());
+
+        assert!(png.chunk_by_type("prVN").is_some());
+        assert!(png.discarded_chunks().is_empty());
+    }
+
+    #[test]
+    fn test_keep_unsafe_override() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("prVN", "provenance").unwrap());
+        png.set_keep_unsafe(true);
+
+        png.replace_image_data(vec![1, 2, 3]).unwrap();
+
+        assert!(png.chunk_by_type("prVN").is_some());
+        assert!(png.discarded_chunks().is_empty());
+    }
+
+    #[test]
+    fn test_replace_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("prVN", "provenance").unwrap());
+        let idat_index = png.chunk_type_to_first_index("IDAT").unwrap();
+
+        png.replace_image_data(vec![1, 2, 3]).unwrap();
+
+        let idats: Vec<&Chunk> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .collect();
+        assert_eq!(idats.len(), 1);
+        assert_eq!(idats[0].data(), &[1, 2, 3]);
+        assert_eq!(png.chunk_type_to_first_index("IDAT"), Some(idat_index));
+        assert_eq!(png.discarded_chunks().len(), 1);
+    }
+
+    #[test]
+    fn test_indices_of() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another middle").unwrap());
+
+        assert_eq!(png.indices_of("miDl"), vec![1, 3]);
+        assert!(png.indices_of("noNe").is_empty());
+    }
+
+    #[test]
+    fn test_remove_all_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another middle").unwrap());
+
+        let removed = png.remove_all_chunks("miDl");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("miDl").is_none());
+    }
+
+    #[test]
+    fn test_replace_chunk() {
+        let mut png = testing_png();
+        let replacement = chunk_from_strings("neWl", "Replacement").unwrap();
+
+        let old = png.replace_chunk(1, replacement).unwrap();
+        assert_eq!(old.chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), "neWl");
+
+        let out_of_range = chunk_from_strings("neWl", "Replacement").unwrap();
+        assert!(png.replace_chunk(3, out_of_range).is_err());
+    }
+
+    #[test]
+    fn test_move_chunk() {
+        let mut png = testing_png();
+        png.move_chunk(2, 0).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, vec!["LASt", "FrSt", "miDl"]);
+        assert!(png.move_chunk(0, 3).is_err());
+    }
+
+    #[test]
+    fn test_retain_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("prVN", "provenance").unwrap());
+        png.retain_chunks(|c| c.chunk_type().to_string() != "FrSt");
+
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("prVN").is_none());
+        assert_eq!(png.discarded_chunks().len(), 1);
+    }
+
+    #[test]
+    fn test_chunk_data_mut() {
+        let mut png = testing_png();
+        {
+            let mut data = png.chunk_data_mut(1).unwrap();
+            data.clear();
+            data.extend_from_slice(b"Rewritten");
+            data.finish().unwrap();
+        }
+
+        let chunk = &png.chunks()[1];
+        let expected = chunk_from_strings("miDl", "Rewritten").unwrap();
+        assert_eq!(chunk.length(), 9);
+        assert_eq!(chunk.crc(), expected.crc());
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(reparsed.chunks()[1].data(), b"Rewritten");
+        assert!(png.chunk_data_mut(3).is_err());
+    }
+
+    #[test]
+    fn test_chunk_data_mut_without_changes() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("prVN", "provenance").unwrap());
+
+        // unchanged bytes are not a critical edit
+        png.chunk_data_mut(0).unwrap().finish().unwrap();
+        assert!(png.chunk_by_type("prVN").is_some());
+
+        // and edits are dropped without finish
+        let mut data = png.chunk_data_mut(0).unwrap();
+        data.push(b'!');
+        drop(data);
+        assert_eq!(png.chunks()[0].data(), b"I am the first chunk");
+        assert!(png.chunk_by_type("prVN").is_some());
+
+        let mut data = png.chunk_data_mut(0).unwrap();
+        data.push(b'!');
+        data.finish().unwrap();
+        assert!(png.chunk_by_type("prVN").is_none());
+    }
+
+    fn corrupt_png_bytes() -> Vec<u8> {
+        let mut bytes = PNG_FILE.to_vec();
+        // flip a bit in the IHDR CRC, then lose the IEND and add garbage
+        bytes[32] ^= 1;
+        bytes.truncate(bytes.len() - 12);
+        bytes.extend_from_slice(&[0, 0, 0, 1, 2]);
+        bytes
+    }
+
+    #[test]
+    fn test_strict_parse_rejects_corruption() {
+        assert!(Png::try_from(corrupt_png_bytes().as_ref()).is_err());
+        assert!(Png::try_from(&PNG_FILE[..PNG_FILE.len() - 3]).is_err());
+        assert!(Png::try_from(&PNG_FILE[..4]).is_err());
+    }
+
+    #[test]
+    fn test_extract_chunk_out_of_range() {
+        for start in [PNG_FILE.len() - 3, PNG_FILE.len(), PNG_FILE.len() + 5] {
+            let err =
+                Png::extract_chunk_from_bytes(start, &PNG_FILE, ParseMode::Strict).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Truncated chunk at offset {}", start)
+            );
+        }
+        let (chunk, end) = Png::extract_chunk_from_bytes(8, &PNG_FILE, ParseMode::Strict).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "IHDR");
+        assert_eq!(end, 33);
+    }
+
+    #[test]
+    fn test_lenient_parse_diagnostics() {
+        let bytes = corrupt_png_bytes();
+        let png = Png::parse(&bytes, ParseMode::Lenient).unwrap();
+
+        let diagnostics = png.diagnostics();
+        assert_eq!(diagnostics.len(), 3);
+        assert!(matches!(
+            &diagnostics[0],
+            Diagnostic::CrcMismatch { index: 0, chunk_type, .. } if chunk_type == "IHDR"
+        ));
+        assert_eq!(
+            diagnostics[1],
+            Diagnostic::TrailingGarbage {
+                offset: bytes.len() - 5,
+                length: 5
+            }
+        );
+        assert_eq!(diagnostics[2], Diagnostic::MissingIend);
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut png = Png::parse(&corrupt_png_bytes(), ParseMode::Lenient).unwrap();
+        let fixed = png.repair().unwrap();
+
+        assert_eq!(fixed.len(), 3);
+        assert!(png.diagnostics().is_empty());
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("teSt", "Message").unwrap());
+
+        let chunks = png.chunks();
+        assert_eq!(chunks[chunks.len() - 2].chunk_type().to_string(), "teSt");
+        assert_eq!(chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_insert_chunk_positions() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let first_idat = png.indices_of("IDAT")[0];
+
+        let index = png
+            .insert_chunk(
+                Position::AfterIhdr,
+                chunk_from_strings("abCd", "first").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(index, 1);
+
+        let index = png
+            .insert_chunk(
+                Position::BeforeIdat,
+                chunk_from_strings("abCd", "second").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(index, first_idat + 1);
+        assert_eq!(png.chunks()[index + 1].chunk_type().to_string(), "IDAT");
+
+        let index = png
+            .insert_chunk(
+                Position::AfterIdat,
+                chunk_from_strings("abCd", "third").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(png.chunks()[index - 1].chunk_type().to_string(), "IDAT");
+
+        let last = png.chunks().len();
+        assert!(png
+            .insert_chunk(
+                Position::Index(last),
+                chunk_from_strings("abCd", "after IEND").unwrap()
+            )
+            .is_err());
+        assert!(png
+            .insert_chunk(
+                Position::BeforeIend,
+                chunk_from_strings("IEND", "").unwrap()
+            )
+            .is_err());
+        let err = png
+            .insert_chunk(
+                Position::Index(0),
+                chunk_from_strings("abCd", "before IHDR").unwrap(),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("IHDR must stay the first chunk"));
+        assert!(png
+            .insert_chunk(
+                Position::Index(1),
+                chunk_from_strings("abCd", "after IHDR").unwrap()
+            )
+            .is_ok());
+    }
+
+    fn capy_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .filter(|t| t != "IDAT")
+            .collect()
+    }
+
+    #[test]
+    fn test_copy_ancillary_safe_to_copy() {
+        let source = Png::try_from(&include_bytes!("../capy.png")[..]).unwrap();
+        let mut destination = Png::try_from(&include_bytes!("../capy.png")[..]).unwrap();
+        destination.retain_chunks(|c| c.chunk_type().is_critical());
+
+        let copied = destination.copy_ancillary_from(&source, |c| c.chunk_type().is_safe_to_copy());
+        assert_eq!(copied, vec!["tEXt", "tEXt"]);
+        assert_eq!(
+            capy_types(&destination),
+            vec!["IHDR", "tEXt", "tEXt", "IEND"]
+        );
+        let last_idat = *destination.indices_of("IDAT").last().unwrap();
+        assert_eq!(destination.indices_of("tEXt")[0], last_idat + 1);
+
+        let again = destination.copy_ancillary_from(&source, |c| c.chunk_type().is_safe_to_copy());
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn test_copy_ancillary_by_type() {
+        let source = Png::try_from(&include_bytes!("../capy.png")[..]).unwrap();
+        let mut destination = Png::try_from(&include_bytes!("../capy.png")[..]).unwrap();
+        destination.retain_chunks(|c| c.chunk_type().is_critical());
+        let time = ChunkType::from_str("tIME").unwrap();
+        destination.append_chunk(Chunk::new(time, vec![7, 232, 1, 1, 0, 0, 0]).unwrap());
+
+        let wanted = ["tIME", "bKGD", "gAMA"];
+        let copied = destination.copy_ancillary_from(&source, |c| {
+            wanted.contains(&c.chunk_type().to_string().as_str())
+        });
+        assert_eq!(copied, vec!["gAMA", "bKGD", "tIME"]);
+
+        // the existing tIME is replaced, and all land before the image data
+        // as they were in the source
+        let types = capy_types(&destination);
+        assert_eq!(types, vec!["IHDR", "gAMA", "bKGD", "tIME", "IEND"]);
+        assert_eq!(
+            destination.chunk_by_type("tIME").unwrap().data(),
+            source.chunk_by_type("tIME").unwrap().data()
+        );
+        assert!(destination.indices_of("tIME")[0] < destination.indices_of("IDAT")[0]);
+    }
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(
+            Position::from_str("before-iend").unwrap(),
+            Position::BeforeIend
+        );
+        assert_eq!(Position::from_str("3").unwrap(), Position::Index(3));
+        assert!(Position::from_str("middle").is_err());
+    }
+
+    #[test]
+    fn test_parse_chunk_records() {
+        let first = chunk_from_strings("abCd", "one").unwrap();
+        let second = chunk_from_strings("tEXt", "Comment\0two").unwrap();
+        let mut bytes = first.as_bytes();
+        bytes.extend(second.as_bytes());
+
+        let chunks = Png::parse_chunk_records(&bytes).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].data(), second.data());
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let err = Png::parse_chunk_records(&bytes).unwrap_err();
+        assert!(err.to_string().starts_with("Chunk record 1 at offset 15"));
+        assert!(Png::parse_chunk_records(&[]).is_err());
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = PNG_FILE.to_vec();
+        let hidden = chunk_from_strings("ruSt", "after the end").unwrap();
+        bytes.extend(hidden.as_bytes());
+
+        let mut png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").map(|c| c.length()), None);
+        assert_eq!(png.trailing_data(), hidden.as_bytes());
+        assert_eq!(png.as_bytes(), bytes);
+
+        let stripped = png.strip_trailing_data();
+        assert_eq!(stripped, hidden.as_bytes());
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+
+        png.set_trailing_data(b"polyglot".to_vec());
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(reparsed.trailing_data(), b"polyglot");
+    }
+
+    #[test]
+    fn test_register_codec_round_trip() {
+        let mut png = testing_png();
+        let bbox = BoundingBox {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 20,
+        };
+        png.register_codec(BoundingBoxCodec).unwrap();
+        png.append_custom(&BoundingBoxCodec, &bbox).unwrap();
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let decoded = reparsed.decode_custom(&BoundingBoxCodec).unwrap();
+        assert_eq!(decoded, vec![bbox]);
+
+        let chunk = png.chunk_by_type("tmBx").unwrap();
+        assert_eq!(png.describe_chunk(chunk).unwrap(), "10x20 at (0, 0)");
+        assert!(png.validate().is_ok());
+    }
+
+    #[test]
+    fn test_register_codec_rejects_invalid_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("tmBx", "bad").unwrap());
+
+        assert!(png.register_codec(BoundingBoxCodec).is_err());
+        assert!(!png.codecs().is_registered("tmBx"));
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
     const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
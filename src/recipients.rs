@@ -0,0 +1,194 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, decode_key_file, encode_key_file, key_id, KEY_ID_LENGTH, KEY_LENGTH};
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+pub const RECIPIENTS_MAGIC: [u8; 4] = *b"pLeK";
+const PUBLIC_KEY_PREFIX: &str = "pnglee-x25519-public";
+const SECRET_KEY_PREFIX: &str = "pnglee-x25519-secret";
+const WRAP_INFO: &[u8] = b"pnglee recipient key wrap";
+// key id, nonce, then the content key and its tag
+const ENTRY_LENGTH: usize = KEY_ID_LENGTH + 24 + KEY_LENGTH + 16;
+const FIXED_HEADER_LENGTH: usize = 4 + 1 + 32 + 2;
+
+pub fn generate_keypair() -> (StaticSecret, PublicKey) {
+    let secret = StaticSecret::random();
+    let public = PublicKey::from(&secret);
+    (secret, public)
+}
+
+pub fn encode_public_key(public_key: &PublicKey) -> String {
+    encode_key_file(PUBLIC_KEY_PREFIX, public_key.as_bytes())
+}
+
+pub fn encode_secret_key(secret_key: &StaticSecret) -> String {
+    encode_key_file(SECRET_KEY_PREFIX, secret_key.as_bytes())
+}
+
+pub fn parse_public_key(contents: &str) -> Result<PublicKey> {
+    Ok(PublicKey::from(decode_key_file(
+        PUBLIC_KEY_PREFIX,
+        contents,
+    )?))
+}
+
+pub fn parse_secret_key(contents: &str) -> Result<StaticSecret> {
+    let bytes = Zeroizing::new(decode_key_file(SECRET_KEY_PREFIX, contents)?);
+    Ok(StaticSecret::from(*bytes))
+}
+
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(&RECIPIENTS_MAGIC)
+}
+
+fn wrap_key(
+    secret: &StaticSecret,
+    their_public: &PublicKey,
+    ephemeral_public: &PublicKey,
+    recipient_public: &PublicKey,
+) -> Result<Zeroizing<[u8; KEY_LENGTH]>> {
+    let shared = secret.diffie_hellman(their_public);
+    if !shared.was_contributory() {
+        return Err(anyhow!("Recipient public key is not usable"));
+    }
+
+    let salt: Vec<u8> = ephemeral_public
+        .as_bytes()
+        .iter()
+        .chain(recipient_public.as_bytes().iter())
+        .copied()
+        .collect();
+    let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(WRAP_INFO, key.as_mut())
+        .map_err(|_| anyhow!("Key wrapping failed"))?;
+    Ok(key)
+}
+
+// layout: magic, version, ephemeral public key, u16 recipient count,
+// one wrapped content key per recipient, then the sealed payload with
+// everything before it as associated data
+pub fn seal_for_recipients(plaintext: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>> {
+    if recipients.is_empty() {
+        return Err(anyhow!("At least one recipient is required"));
+    }
+    let count = u16::try_from(recipients.len()).map_err(|_| anyhow!("Too many recipients"))?;
+
+    let (ephemeral_secret, ephemeral_public) = generate_keypair();
+    let content_key = Zeroizing::new(crypto::random_bytes::<KEY_LENGTH>());
+
+    let mut header = RECIPIENTS_MAGIC.to_vec();
+    header.push(crypto::VERSION);
+    header.extend_from_slice(ephemeral_public.as_bytes());
+    header.extend_from_slice(&count.to_be_bytes());
+
+    for recipient in recipients {
+        let key = wrap_key(&ephemeral_secret, recipient, &ephemeral_public, recipient)?;
+        let id = key_id(recipient.as_bytes());
+        header.extend(crypto::seal(&key, &id, content_key.as_ref())?);
+    }
+
+    crypto::seal(&content_key, &header, plaintext)
+}
+
+pub fn open_with_secret(bytes: &[u8], secret: &StaticSecret) -> Result<Vec<u8>> {
+    if !is_sealed(bytes) || bytes.len() < FIXED_HEADER_LENGTH {
+        return Err(anyhow!("Payload is not sealed to recipients"));
+    }
+    if bytes[4] != crypto::VERSION {
+        return Err(anyhow!("Unsupported encryption version {}", bytes[4]));
+    }
+
+    let ephemeral_public = PublicKey::from(<[u8; 32]>::try_from(&bytes[5..37]).unwrap());
+    let count = u16::from_be_bytes([bytes[37], bytes[38]]) as usize;
+    let header_length = FIXED_HEADER_LENGTH + count * ENTRY_LENGTH;
+    if bytes.len() < header_length {
+        return Err(anyhow!("Sealed payload is truncated"));
+    }
+    let (header, sealed) = bytes.split_at(header_length);
+
+    let my_public = PublicKey::from(secret);
+    let my_id = key_id(my_public.as_bytes());
+    let entry = header[FIXED_HEADER_LENGTH..]
+        .chunks_exact(ENTRY_LENGTH)
+        .find(|entry| entry[..KEY_ID_LENGTH] == my_id)
+        .ok_or_else(|| anyhow!("Payload was not sealed to this key"))?;
+
+    let key = wrap_key(secret, &ephemeral_public, &ephemeral_public, &my_public)?;
+    let content_key: [u8; KEY_LENGTH] =
+        crypto::open(&key, &entry[..KEY_ID_LENGTH], &entry[KEY_ID_LENGTH..])?
+            .try_into()
+            .map_err(|_| anyhow!("Wrapped key has the wrong length"))?;
+    let content_key = Zeroizing::new(content_key);
+
+    crypto::open(&content_key, header, sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_for_multiple_recipients() {
+        let (alice_secret, alice_public) = generate_keypair();
+        let (bob_secret, bob_public) = generate_keypair();
+        let (eve_secret, _) = generate_keypair();
+
+        let sealed = seal_for_recipients(b"for your eyes", &[alice_public, bob_public]).unwrap();
+        assert!(is_sealed(&sealed));
+
+        assert_eq!(
+            open_with_secret(&sealed, &alice_secret).unwrap(),
+            b"for your eyes"
+        );
+        assert_eq!(
+            open_with_secret(&sealed, &bob_secret).unwrap(),
+            b"for your eyes"
+        );
+
+        let err = open_with_secret(&sealed, &eve_secret).unwrap_err();
+        assert_eq!(err.to_string(), "Payload was not sealed to this key");
+    }
+
+    #[test]
+    fn test_tampered_sealed_payload() {
+        let (secret, public) = generate_keypair();
+        let sealed = seal_for_recipients(b"for your eyes", &[public]).unwrap();
+
+        let mut tampered_body = sealed.clone();
+        let last = tampered_body.len() - 1;
+        tampered_body[last] ^= 1;
+        assert!(open_with_secret(&tampered_body, &secret).is_err());
+
+        // the ephemeral key is part of the header
+        let mut tampered_header = sealed;
+        tampered_header[10] ^= 1;
+        assert!(open_with_secret(&tampered_header, &secret).is_err());
+    }
+
+    #[test]
+    fn test_no_recipients() {
+        assert!(seal_for_recipients(b"nobody", &[]).is_err());
+    }
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let (secret, public) = generate_keypair();
+
+        let public_file = encode_public_key(&public);
+        assert!(public_file.starts_with("pnglee-x25519-public "));
+        assert_eq!(parse_public_key(&public_file).unwrap(), public);
+
+        let secret_file = encode_secret_key(&secret);
+        let parsed = parse_secret_key(&secret_file).unwrap();
+        assert_eq!(parsed.to_bytes(), secret.to_bytes());
+
+        assert!(parse_secret_key(&public_file).is_err());
+        assert!(parse_public_key("pnglee-x25519-public 00ff").is_err());
+    }
+}
@@ -0,0 +1,912 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use serde::{Serialize, Serializer};
+
+use crate::chunk::Chunk;
+use crate::diff::{ChunkChange, ImageDataDiff, PixelDiff};
+use crate::known_chunk::KnownChunk;
+use crate::output::Report;
+use crate::png::Png;
+
+const PREVIEW_CHARS: usize = 40;
+
+// the results each command hands back, printed as text or serialised as
+// JSON depending on --format
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChunkInfo {
+    pub index: usize,
+    // byte offset of the chunk's length field in the file
+    pub offset: usize,
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: String,
+    pub crc_valid: bool,
+    pub critical: bool,
+    pub public: bool,
+    pub reserved_valid: bool,
+    pub safe_to_copy: bool,
+}
+
+impl ChunkInfo {
+    pub fn from_png(png: &Png) -> Vec<ChunkInfo> {
+        let mut offset = Png::STANDARD_HEADER.len();
+        png.chunks()
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let chunk_type = chunk.chunk_type();
+                let info = ChunkInfo {
+                    index,
+                    offset,
+                    chunk_type: chunk_type.to_string(),
+                    length: chunk.length(),
+                    crc: format!("{:08x}", chunk.crc()),
+                    crc_valid: chunk.is_crc_valid(),
+                    critical: chunk_type.is_critical(),
+                    public: chunk_type.is_public(),
+                    reserved_valid: chunk_type.is_reserved_bit_valid(),
+                    safe_to_copy: chunk_type.is_safe_to_copy(),
+                };
+                offset += chunk.length() as usize + 12;
+                info
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListedChunk {
+    #[serde(flatten)]
+    pub info: ChunkInfo,
+    pub preview: String,
+}
+
+impl ListedChunk {
+    pub fn from_png(png: &Png) -> Vec<ListedChunk> {
+        ChunkInfo::from_png(png)
+            .into_iter()
+            .zip(png.chunks())
+            .map(|(info, chunk)| ListedChunk {
+                info,
+                preview: ListedChunk::preview(png, chunk),
+            })
+            .collect()
+    }
+
+    // a registered codec or typed summary where one parses, falling back
+    // to the raw data
+    fn preview(png: &Png, chunk: &Chunk) -> String {
+        let summary = match png.codecs().describe(chunk) {
+            Some(Ok(description)) => description,
+            _ => match chunk.parse_known() {
+                Ok(KnownChunk::Unknown(_)) | Err(_) => return chunk.preview(PREVIEW_CHARS),
+                Ok(known) => known.to_string(),
+            },
+        };
+        let mut preview: String = summary
+            .chars()
+            .take(PREVIEW_CHARS)
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        if summary.chars().count() > PREVIEW_CHARS {
+            preview.push_str("...");
+        }
+        preview
+    }
+
+    // one letter per property, C critical, P public, R reserved bit set
+    // and S safe to copy
+    pub fn flags(&self) -> String {
+        [
+            (self.info.critical, 'C'),
+            (self.info.public, 'P'),
+            (!self.info.reserved_valid, 'R'),
+            (self.info.safe_to_copy, 'S'),
+        ]
+        .iter()
+        .map(|&(set, letter)| if set { letter } else { '-' })
+        .collect()
+    }
+}
+
+impl Display for ListedChunk {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>5} {:>10} {} {} {:>10} {} {:<3} {}",
+            self.info.index,
+            self.info.offset,
+            self.info.chunk_type,
+            self.flags(),
+            self.info.length,
+            self.info.crc,
+            if self.info.crc_valid { "ok" } else { "BAD" },
+            self.preview
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListReport {
+    pub chunks: Vec<ListedChunk>,
+    pub trailing_bytes: usize,
+}
+
+impl Display for ListReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>10} type flag {:>10} crc          preview",
+            "index", "offset", "length"
+        )?;
+        for chunk in &self.chunks {
+            writeln!(f, "{}", chunk)?;
+        }
+        if self.trailing_bytes > 0 {
+            writeln!(f, "{} bytes of data after IEND", self.trailing_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Report for ListReport {
+    // just the rows, without the header
+    fn quiet(&self) -> Option<String> {
+        let rows: Vec<String> = self.chunks.iter().map(|c| c.to_string()).collect();
+        Some(rows.join("\n"))
+    }
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+fn serialize_optional_hex<S: Serializer>(
+    bytes: &Option<Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match bytes {
+        Some(bytes) => serialize_hex(bytes, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+// 16 bytes a line with the offset and an ASCII column, as hexdump -C
+pub fn hex_dump(data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(line, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let (left, right) = hex.split_at(hex.len().min(8));
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}  {:<23}  {:<23}  |{}|",
+                line * 16,
+                left.join(" "),
+                right.join(" "),
+                ascii
+            )
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DumpReport {
+    pub chunk: ChunkInfo,
+    #[serde(serialize_with = "serialize_hex")]
+    pub data: Vec<u8>,
+}
+
+impl Display for DumpReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Chunk {} {}, {} bytes at offset {}, CRC {} {}",
+            self.chunk.index,
+            self.chunk.chunk_type,
+            self.chunk.length,
+            self.chunk.offset,
+            self.chunk.crc,
+            if self.chunk.crc_valid { "ok" } else { "BAD" }
+        )?;
+        for line in hex_dump(&self.data) {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Report for DumpReport {
+    fn quiet(&self) -> Option<String> {
+        Some(hex_dump(&self.data).join("\n"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExtractReport {
+    pub chunk: ChunkInfo,
+    // whether the bytes are the whole record rather than just the data
+    pub record: bool,
+    pub length: usize,
+    pub written_to: Option<String>,
+    // only kept when there is no output file, for stdout
+    #[serde(serialize_with = "serialize_optional_hex")]
+    pub data: Option<Vec<u8>>,
+}
+
+impl Display for ExtractReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(out) = &self.written_to {
+            writeln!(
+                f,
+                "Wrote {} bytes of {} chunk {} to {}",
+                self.length, self.chunk.chunk_type, self.chunk.index, out
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Report for ExtractReport {
+    fn raw(&self) -> Option<&[u8]> {
+        self.data.as_deref()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoveredChunk {
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+}
+
+impl CoveredChunk {
+    pub fn from_pairs(covered: Vec<(usize, String)>) -> Vec<CoveredChunk> {
+        covered
+            .into_iter()
+            .map(|(index, chunk_type)| CoveredChunk { index, chunk_type })
+            .collect()
+    }
+}
+
+// what happened when a changed file was written out
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WriteReport {
+    pub output: String,
+    // unsafe-to-copy chunks dropped after a critical chunk edit
+    pub discarded: Vec<String>,
+    pub stripped_trailing_bytes: usize,
+}
+
+impl Display for WriteReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for chunk_type in &self.discarded {
+            writeln!(
+                f,
+                "Discarded unsafe-to-copy chunk {} after critical chunk edit",
+                chunk_type
+            )?;
+        }
+        if self.stripped_trailing_bytes > 0 {
+            writeln!(
+                f,
+                "Stripped {} bytes after IEND",
+                self.stripped_trailing_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PayloadSource {
+    // only the length, as the message may be a secret
+    Message { length: usize },
+    File { path: String, length: usize },
+}
+
+impl Display for PayloadSource {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            PayloadSource::Message { length } => {
+                writeln!(f, "Encoding file with a {} byte message", length)
+            }
+            PayloadSource::File { path, length } => {
+                writeln!(f, "Encoding file with {} bytes from {}", length, path)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EncodeReport {
+    pub chunk_type: String,
+    pub source: PayloadSource,
+    // size of the payload as stored, after compression and encryption
+    pub stored_bytes: usize,
+    pub chunks: usize,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for EncodeReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)?;
+        if self.chunks > 1 {
+            writeln!(f, "Splitting payload across {} chunks", self.chunks)?;
+        }
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for EncodeReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HideReport {
+    pub source: PayloadSource,
+    pub stored_bytes: usize,
+    pub capacity: usize,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for HideReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)?;
+        writeln!(
+            f,
+            "Pixel capacity is {} bytes, payload needs {}",
+            self.capacity, self.stored_bytes
+        )?;
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for HideReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PayloadReport {
+    pub name: Option<String>,
+    pub mime: Option<String>,
+    pub length: usize,
+    // the payload as text, if it is valid UTF-8
+    pub message: Option<String>,
+    pub written_to: Option<String>,
+}
+
+impl Display for PayloadReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            writeln!(f, "Original file name: {}", name)?;
+        }
+        if let Some(mime) = &self.mime {
+            writeln!(f, "MIME type: {}", mime)?;
+        }
+        match (&self.written_to, &self.message) {
+            (Some(out), _) => writeln!(f, "Wrote {} bytes to {}", self.length, out),
+            (None, Some(message)) => writeln!(f, "Super secret message: {}", message),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Report for PayloadReport {
+    // the message itself, unless it went to a file
+    fn quiet(&self) -> Option<String> {
+        match &self.written_to {
+            Some(_) => None,
+            None => self.message.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodeReport {
+    pub chunk_type: String,
+    // the chunks the payload was read from
+    pub chunks: Vec<ChunkInfo>,
+    pub payload: PayloadReport,
+}
+
+impl Display for DecodeReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.payload)
+    }
+}
+
+impl Report for DecodeReport {
+    fn quiet(&self) -> Option<String> {
+        self.payload.quiet()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemoveReport {
+    pub removed: ChunkInfo,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for RemoveReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Removing for chunk type {}", self.removed.chunk_type)?;
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for RemoveReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InjectReport {
+    // where the new chunks are in the written file
+    pub injected: Vec<ChunkInfo>,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for InjectReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for chunk in &self.injected {
+            writeln!(
+                f,
+                "Injected {} chunk of {} bytes at index {}",
+                chunk.chunk_type, chunk.length, chunk.index
+            )?;
+        }
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for InjectReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CopyMetaReport {
+    pub source: String,
+    pub copied: Vec<String>,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for CopyMetaReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.copied.is_empty() {
+            writeln!(f, "No matching chunks to copy from {}", self.source)?;
+        } else {
+            writeln!(
+                f,
+                "Copied {} chunks from {}: {}",
+                self.copied.len(),
+                self.source,
+                self.copied.join(", ")
+            )?;
+        }
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for CopyMetaReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StripReport {
+    // as they were in the input file
+    pub removed: Vec<ChunkInfo>,
+    // whole records, including length, type and CRC
+    pub bytes_removed: usize,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for StripReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for chunk in &self.removed {
+            writeln!(
+                f,
+                "Removed {} chunk of {} bytes",
+                chunk.chunk_type, chunk.length
+            )?;
+        }
+        writeln!(
+            f,
+            "Stripped {} chunks, {} bytes",
+            self.removed.len(),
+            self.bytes_removed
+        )?;
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for StripReport {
+    fn quiet(&self) -> Option<String> {
+        Some(self.bytes_removed.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PixelComparison {
+    Compared(PixelDiff),
+    Skipped { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffReport {
+    pub a: String,
+    pub b: String,
+    pub identical: bool,
+    pub changes: Vec<ChunkChange>,
+    pub image_data: ImageDataDiff,
+    pub pixels: Option<PixelComparison>,
+}
+
+impl Display for ChunkChange {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ChunkChange::Removed {
+                chunk_type,
+                index_a,
+            } => write!(f, "- {} at index {}", chunk_type, index_a),
+            ChunkChange::Added {
+                chunk_type,
+                index_b,
+            } => write!(f, "+ {} at index {}", chunk_type, index_b),
+            ChunkChange::Modified {
+                chunk_type,
+                index_a,
+                index_b,
+                details,
+            } => {
+                write!(f, "~ {} at index {} -> {}", chunk_type, index_a, index_b)?;
+                for detail in details {
+                    write!(f, "\n    {}", detail)?;
+                }
+                Ok(())
+            }
+            ChunkChange::Moved {
+                chunk_type,
+                index_a,
+                index_b,
+            } => write!(
+                f,
+                "> {} moved from index {} to {}",
+                chunk_type, index_a, index_b
+            ),
+        }
+    }
+}
+
+impl Display for DiffReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "--- {}", self.a)?;
+        writeln!(f, "+++ {}", self.b)?;
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+
+        let data = &self.image_data;
+        if data.identical {
+            writeln!(f, "Image data: identical")?;
+        } else {
+            writeln!(
+                f,
+                "Image data: {} bytes in {} IDAT chunks -> {} bytes in {} IDAT chunks",
+                data.bytes_a, data.chunks_a, data.bytes_b, data.chunks_b
+            )?;
+        }
+
+        match &self.pixels {
+            Some(PixelComparison::Compared(pixels)) => writeln!(
+                f,
+                "Pixels: {} of {} differ, largest sample difference {}",
+                pixels.differing_pixels, pixels.pixels, pixels.max_sample_difference
+            )?,
+            Some(PixelComparison::Skipped { reason }) => {
+                writeln!(f, "Pixels: not compared, {}", reason)?
+            }
+            None => {}
+        }
+
+        if self.identical {
+            writeln!(f, "No differences")?;
+        }
+        Ok(())
+    }
+}
+
+impl Report for DiffReport {
+    fn quiet(&self) -> Option<String> {
+        Some(self.identical.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RepairReport {
+    pub file_path: String,
+    pub fixes: Vec<String>,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for RepairReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for fix in &self.fixes {
+            writeln!(f, "Repaired: {}", fix)?;
+        }
+        if self.fixes.is_empty() {
+            writeln!(f, "Nothing to repair in {}", self.file_path)?;
+        }
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for RepairReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeygenReport {
+    pub key_id: String,
+    pub signing: bool,
+    pub secret_path: String,
+    pub public_path: String,
+}
+
+impl Display for KeygenReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Wrote key {} to {} and {}",
+            self.key_id, self.secret_path, self.public_path
+        )
+    }
+}
+
+impl Report for KeygenReport {
+    fn quiet(&self) -> Option<String> {
+        Some(self.key_id.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignReport {
+    pub key_id: String,
+    pub covered: Vec<CoveredChunk>,
+    #[serde(flatten)]
+    pub written: WriteReport,
+}
+
+impl Display for SignReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Signed {} chunks with key {}",
+            self.covered.len(),
+            self.key_id
+        )?;
+        write!(f, "{}", self.written)
+    }
+}
+
+impl Report for SignReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub key_id: String,
+    pub covered: Vec<CoveredChunk>,
+}
+
+impl Display for VerifyReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Valid signature by key {} covering {} chunks:",
+            self.key_id,
+            self.covered.len()
+        )?;
+        for chunk in &self.covered {
+            writeln!(f, "  {:>4} {}", chunk.index, chunk.chunk_type)?;
+        }
+        Ok(())
+    }
+}
+
+impl Report for VerifyReport {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CapacityReport {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_channel: u8,
+    pub capacity: usize,
+}
+
+impl Display for CapacityReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{}x{} pixels, {} bits per channel: {} bytes of pixel capacity",
+            self.width, self.height, self.bits_per_channel, self.capacity
+        )
+    }
+}
+
+impl Report for CapacityReport {
+    fn quiet(&self) -> Option<String> {
+        Some(self.capacity.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrintReport {
+    pub chunks: Vec<ChunkInfo>,
+    // descriptions of the private ancillary chunks
+    pub descriptions: Vec<String>,
+    pub trailing_bytes: usize,
+    pub dumped_to: Option<String>,
+}
+
+impl Display for PrintReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for description in &self.descriptions {
+            writeln!(f, "{}", description)?;
+        }
+        if self.trailing_bytes > 0 {
+            writeln!(f, "{} bytes of data after IEND", self.trailing_bytes)?;
+        }
+        if let Some(dump_path) = &self.dumped_to {
+            writeln!(
+                f,
+                "Wrote {} trailing bytes to {}",
+                self.trailing_bytes, dump_path
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Report for PrintReport {
+    // the chunk descriptions are the result, the rest is chatter
+    fn quiet(&self) -> Option<String> {
+        if self.descriptions.is_empty() {
+            return None;
+        }
+        Some(self.descriptions.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PNG_FILE: &[u8] = include_bytes!("../capy.png");
+
+    #[test]
+    fn test_chunk_info_offsets() {
+        let png = Png::try_from(PNG_FILE).unwrap();
+        let infos = ChunkInfo::from_png(&png);
+
+        assert_eq!(infos.len(), png.chunks().len());
+        assert_eq!(infos[0].offset, 8);
+        assert_eq!(infos[0].chunk_type, "IHDR");
+        assert_eq!(infos[1].offset, 8 + 12 + 13);
+        let last = infos.last().unwrap();
+        assert_eq!(last.offset + 12, PNG_FILE.len());
+        assert!(infos.iter().all(|info| info.crc_valid));
+    }
+
+    #[test]
+    fn test_chunk_info_json() {
+        let png = Png::try_from(PNG_FILE).unwrap();
+        let ihdr = &ChunkInfo::from_png(&png)[0];
+        let value = serde_json::to_value(ihdr).unwrap();
+
+        assert_eq!(value["type"], "IHDR");
+        assert_eq!(value["length"], 13);
+        assert_eq!(value["critical"], true);
+        assert_eq!(value["crc"], format!("{:08x}", png.chunks()[0].crc()));
+    }
+
+    #[test]
+    fn test_listed_chunks() {
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        png.append_chunk(
+            Chunk::new(
+                crate::chunk_type::ChunkType::try_from(*b"biNa").unwrap(),
+                vec![0xde, 0xad, 0xbe, 0xef],
+            )
+            .unwrap(),
+        );
+        let listed = ListedChunk::from_png(&png);
+
+        assert_eq!(listed[0].flags(), "CP--");
+        assert!(listed[0].preview.starts_with(&format!(
+            "{}x",
+            u32::from_be_bytes(png.chunks()[0].data()[..4].try_into().unwrap())
+        )));
+        let binary = listed.iter().find(|c| c.info.chunk_type == "biNa").unwrap();
+        assert_eq!(binary.flags(), "---S");
+        assert_eq!(binary.preview, "deadbeef");
+        assert!(binary.to_string().contains("biNa ---S"));
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let data: Vec<u8> = b"date:create=2023\x00\x01\xff".to_vec();
+        assert_eq!(
+            hex_dump(&data),
+            vec![
+                "00000000  64 61 74 65 3a 63 72 65  61 74 65 3d 32 30 32 33  |date:create=2023|",
+                "00000010  00 01 ff                                          |...|",
+            ]
+        );
+        assert!(hex_dump(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_flattened_write_report() {
+        let report = SignReport {
+            key_id: "abcd".to_string(),
+            covered: vec![],
+            written: WriteReport {
+                output: "out.png".to_string(),
+                discarded: vec![],
+                stripped_trailing_bytes: 4,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "key_id": "abcd",
+                "covered": [],
+                "output": "out.png",
+                "discarded": [],
+                "stripped_trailing_bytes": 4,
+            })
+        );
+        assert_eq!(
+            report.to_string(),
+            "Signed 0 chunks with key abcd\nStripped 4 bytes after IEND\n"
+        );
+        assert_eq!(report.quiet(), None);
+    }
+
+    #[test]
+    fn test_message_source_has_no_text() {
+        let source = PayloadSource::Message { length: 10 };
+        assert_eq!(
+            serde_json::to_value(&source).unwrap(),
+            serde_json::json!({ "kind": "message", "length": 10 })
+        );
+        assert_eq!(source.to_string(), "Encoding file with a 10 byte message\n");
+    }
+
+    #[test]
+    fn test_quiet_payload() {
+        let mut payload = PayloadReport {
+            name: Some("note.txt".to_string()),
+            mime: None,
+            length: 5,
+            message: Some("hello".to_string()),
+            written_to: None,
+        };
+        assert_eq!(
+            payload.to_string(),
+            "Original file name: note.txt\nSuper secret message: hello\n"
+        );
+        assert_eq!(payload.quiet().as_deref(), Some("hello"));
+
+        payload.written_to = Some("note.txt".to_string());
+        assert_eq!(payload.quiet(), None);
+    }
+}
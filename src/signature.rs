@@ -0,0 +1,385 @@
+use std::str::FromStr;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crypto::{self, KEY_ID_LENGTH};
+use crate::png::Png;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+// ancillary, private and unsafe to copy, so editors that change the
+// critical chunks it covers drop it rather than keep a stale signature
+pub const SIGNATURE_CHUNK_TYPE: &str = "sgNT";
+const SIGNING_DOMAIN: &[u8] = b"pnglee png signature";
+const VERSION: u8 = 1;
+const PUBLIC_KEY_PREFIX: &str = "pnglee-ed25519-public";
+const SECRET_KEY_PREFIX: &str = "pnglee-ed25519-secret";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Coverage {
+    Critical,
+    Types(Vec<String>),
+}
+
+impl Coverage {
+    // the signature chunk can never cover itself
+    pub fn covers(&self, chunk_type: &ChunkType) -> bool {
+        let type_string = chunk_type.to_string();
+        if type_string == SIGNATURE_CHUNK_TYPE {
+            return false;
+        }
+        match self {
+            Coverage::Critical => chunk_type.is_critical(),
+            Coverage::Types(types) => types.contains(&type_string),
+        }
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            Coverage::Critical => Ok(vec![0]),
+            Coverage::Types(types) => {
+                let mut bytes = vec![1];
+                let count =
+                    u16::try_from(types.len()).map_err(|_| anyhow!("Too many chunk types"))?;
+                bytes.extend_from_slice(&count.to_be_bytes());
+                for chunk_type in types {
+                    bytes.extend(ChunkType::from_str(chunk_type)?.bytes());
+                }
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SignatureRecord {
+    pub key_id: [u8; KEY_ID_LENGTH],
+    pub coverage: Coverage,
+    pub signature: Signature,
+}
+
+impl SignatureRecord {
+    // everything before the signature itself, which is also signed
+    fn header(key_id: &[u8; KEY_ID_LENGTH], coverage: &Coverage) -> Result<Vec<u8>> {
+        let mut header = vec![VERSION];
+        header.extend_from_slice(key_id);
+        header.extend(coverage.to_bytes()?);
+        Ok(header)
+    }
+
+    // layout: version, key id, coverage mode, optional u16 type count
+    // and types, then the 64 byte signature
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = SignatureRecord::header(&self.key_id, &self.coverage)?;
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SignatureRecord> {
+        let truncated = || anyhow!("Signature chunk is truncated");
+        if bytes.len() < 1 + KEY_ID_LENGTH + 1 + Signature::BYTE_SIZE {
+            return Err(truncated());
+        }
+        if bytes[0] != VERSION {
+            return Err(anyhow!("Unsupported signature version {}", bytes[0]));
+        }
+
+        let key_id: [u8; KEY_ID_LENGTH] = bytes[1..1 + KEY_ID_LENGTH].try_into().unwrap();
+        let mut rest = &bytes[1 + KEY_ID_LENGTH..];
+        let coverage = match rest[0] {
+            0 => {
+                rest = &rest[1..];
+                Coverage::Critical
+            }
+            1 => {
+                if rest.len() < 3 {
+                    return Err(truncated());
+                }
+                let count = u16::from_be_bytes([rest[1], rest[2]]) as usize;
+                let types_end = 3 + count * 4;
+                if rest.len() < types_end {
+                    return Err(truncated());
+                }
+                let types = rest[3..types_end]
+                    .chunks_exact(4)
+                    .map(|t| Ok(ChunkType::try_from(<[u8; 4]>::try_from(t)?)?.to_string()))
+                    .collect::<Result<Vec<String>>>()?;
+                rest = &rest[types_end..];
+                Coverage::Types(types)
+            }
+            other => return Err(anyhow!("Unknown signature coverage mode {}", other)),
+        };
+
+        let signature_bytes: [u8; Signature::BYTE_SIZE] = rest
+            .try_into()
+            .map_err(|_| anyhow!("Signature must be {} bytes", Signature::BYTE_SIZE))?;
+
+        Ok(SignatureRecord {
+            key_id,
+            coverage,
+            signature: Signature::from_bytes(&signature_bytes),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Verification {
+    pub key_id: String,
+    pub coverage: Coverage,
+    // index and type of every chunk the signature covers
+    pub covered: Vec<(usize, String)>,
+}
+
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::from_bytes(&crypto::random_bytes())
+}
+
+pub fn encode_signing_key(key: &SigningKey) -> String {
+    crypto::encode_key_file(SECRET_KEY_PREFIX, key.as_bytes())
+}
+
+pub fn encode_verifying_key(key: &VerifyingKey) -> String {
+    crypto::encode_key_file(PUBLIC_KEY_PREFIX, key.as_bytes())
+}
+
+pub fn parse_signing_key(contents: &str) -> Result<SigningKey> {
+    let bytes = Zeroizing::new(crypto::decode_key_file(SECRET_KEY_PREFIX, contents)?);
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+pub fn parse_verifying_key(contents: &str) -> Result<VerifyingKey> {
+    let bytes = crypto::decode_key_file(PUBLIC_KEY_PREFIX, contents)?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+pub fn covered_chunks(png: &Png, coverage: &Coverage) -> Vec<(usize, String)> {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| coverage.covers(c.chunk_type()))
+        .map(|(i, c)| (i, c.chunk_type().to_string()))
+        .collect()
+}
+
+// each covered chunk contributes its type, length and data in file order
+fn digest(header: &[u8], png: &Png, coverage: &Coverage) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(SIGNING_DOMAIN);
+    hasher.update(header);
+    for chunk in png
+        .chunks()
+        .iter()
+        .filter(|c| coverage.covers(c.chunk_type()))
+    {
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.data());
+    }
+    hasher.finalize().to_vec()
+}
+
+// replaces any existing signature by the same key, leaving other
+// signers' in place, and returns the chunks now covered
+pub fn sign_png(
+    png: &mut Png,
+    key: &SigningKey,
+    coverage: Coverage,
+) -> Result<Vec<(usize, String)>> {
+    let key_id = crypto::key_id(key.verifying_key().as_bytes());
+    png.retain_chunks(|c| {
+        c.chunk_type().to_string() != SIGNATURE_CHUNK_TYPE
+            || SignatureRecord::from_bytes(c.data()).map_or(true, |r| r.key_id != key_id)
+    });
+
+    let header = SignatureRecord::header(&key_id, &coverage)?;
+    let record = SignatureRecord {
+        key_id,
+        signature: key.sign(&digest(&header, png, &coverage)),
+        coverage,
+    };
+
+    png.append_chunk(Chunk::new(
+        ChunkType::from_str(SIGNATURE_CHUNK_TYPE)?,
+        record.to_bytes()?,
+    )?);
+    Ok(covered_chunks(png, &record.coverage))
+}
+
+pub fn verify_png(png: &Png, key: &VerifyingKey) -> Result<Verification> {
+    let key_id = crypto::key_id(key.as_bytes());
+    let signature_chunks = png.chunks_by_type(SIGNATURE_CHUNK_TYPE);
+    if signature_chunks.is_empty() {
+        return Err(anyhow!("No signature found"));
+    }
+
+    // records that can't be read, e.g. from another tool using the same
+    // chunk type, are skipped as sign_png skips them
+    let records: Vec<Result<SignatureRecord>> = signature_chunks
+        .iter()
+        .map(|c| SignatureRecord::from_bytes(c.data()))
+        .collect();
+    let unreadable = records.iter().filter(|r| r.is_err()).count();
+    let record = records
+        .into_iter()
+        .flatten()
+        .find(|r| r.key_id == key_id)
+        .ok_or_else(|| match unreadable {
+            0 => anyhow!("No signature by key {}", hex::encode(key_id)),
+            _ => anyhow!(
+                "No signature by key {}, {} unreadable signature chunks skipped",
+                hex::encode(key_id),
+                unreadable
+            ),
+        })?;
+
+    let header = SignatureRecord::header(&record.key_id, &record.coverage)?;
+    key.verify(&digest(&header, png, &record.coverage), &record.signature)
+        .map_err(|_| anyhow!("Signature by key {} is not valid", hex::encode(key_id)))?;
+
+    Ok(Verification {
+        key_id: hex::encode(key_id),
+        covered: covered_chunks(png, &record.coverage),
+        coverage: record.coverage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_FILE: &[u8] = include_bytes!("../capy.png");
+
+    fn text_chunk(text: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            text.as_bytes().to_vec(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = generate_signing_key();
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+
+        let covered = sign_png(&mut png, &key, Coverage::Critical).unwrap();
+        assert!(covered.iter().any(|(_, t)| t == "IHDR"));
+        assert!(covered.iter().all(|(_, t)| t != "sgNT"));
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let verification = verify_png(&reparsed, &key.verifying_key()).unwrap();
+        assert_eq!(verification.covered, covered);
+        assert_eq!(verification.coverage, Coverage::Critical);
+    }
+
+    #[test]
+    fn test_uncovered_change_still_verifies() {
+        let key = generate_signing_key();
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        sign_png(&mut png, &key, Coverage::Critical).unwrap();
+
+        png.append_chunk(text_chunk("Comment\0added later"));
+        assert!(verify_png(&png, &key.verifying_key()).is_ok());
+    }
+
+    #[test]
+    fn test_covered_change_fails() {
+        let key = generate_signing_key();
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        png.append_chunk(text_chunk("Author\0pipeline"));
+        let coverage = Coverage::Types(vec!["IHDR".to_string(), "tEXt".to_string()]);
+        sign_png(&mut png, &key, coverage).unwrap();
+
+        let index = png.indices_of("tEXt")[0];
+        png.replace_chunk(index, text_chunk("Author\0someone else"))
+            .unwrap();
+
+        let err = verify_png(&png, &key.verifying_key()).unwrap_err();
+        assert!(err.to_string().contains("is not valid"));
+    }
+
+    #[test]
+    fn test_two_signers() {
+        let first = generate_signing_key();
+        let second = generate_signing_key();
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+
+        sign_png(&mut png, &first, Coverage::Critical).unwrap();
+        sign_png(&mut png, &second, Coverage::Critical).unwrap();
+        // signing again replaces only that key's signature
+        sign_png(&mut png, &first, Coverage::Critical).unwrap();
+
+        assert_eq!(png.chunks_by_type(SIGNATURE_CHUNK_TYPE).len(), 2);
+        assert!(verify_png(&png, &first.verifying_key()).is_ok());
+        assert!(verify_png(&png, &second.verifying_key()).is_ok());
+    }
+
+    #[test]
+    fn test_corrupt_signature_chunk_skipped() {
+        let key = generate_signing_key();
+        let other = generate_signing_key();
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        sign_png(&mut png, &key, Coverage::Critical).unwrap();
+        png.append_chunk(
+            Chunk::new(
+                ChunkType::from_str(SIGNATURE_CHUNK_TYPE).unwrap(),
+                b"not a signature".to_vec(),
+            )
+            .unwrap(),
+        );
+
+        assert!(verify_png(&png, &key.verifying_key()).is_ok());
+        let err = verify_png(&png, &other.verifying_key()).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("1 unreadable signature chunks skipped"));
+
+        // signing again keeps the chunk it can't read
+        sign_png(&mut png, &key, Coverage::Critical).unwrap();
+        assert_eq!(png.chunks_by_type(SIGNATURE_CHUNK_TYPE).len(), 2);
+        assert!(verify_png(&png, &key.verifying_key()).is_ok());
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let key = generate_signing_key();
+        let other = generate_signing_key();
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        sign_png(&mut png, &key, Coverage::Critical).unwrap();
+
+        let err = verify_png(&png, &other.verifying_key()).unwrap_err();
+        assert!(err.to_string().starts_with("No signature by key"));
+    }
+
+    #[test]
+    fn test_critical_edit_drops_signature() {
+        let key = generate_signing_key();
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        sign_png(&mut png, &key, Coverage::Critical).unwrap();
+
+        png.replace_image_data(vec![1, 2, 3]).unwrap();
+        assert!(png.chunk_by_type(SIGNATURE_CHUNK_TYPE).is_none());
+    }
+
+    #[test]
+    fn test_key_files() {
+        let key = generate_signing_key();
+        let secret_file = encode_signing_key(&key);
+        let public_file = encode_verifying_key(&key.verifying_key());
+
+        assert_eq!(
+            parse_signing_key(&secret_file).unwrap().to_bytes(),
+            key.to_bytes()
+        );
+        assert_eq!(
+            parse_verifying_key(&public_file).unwrap(),
+            key.verifying_key()
+        );
+        assert!(parse_verifying_key(&secret_file).is_err());
+    }
+}
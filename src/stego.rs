@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+use crate::pixels::Image;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+// written into the pixels ahead of the payload, followed by the u32
+// payload length
+pub const LSB_MAGIC: [u8; 4] = *b"pLeL";
+const HEADER_LENGTH: usize = 8;
+const ORDER_DOMAIN: &[u8] = b"pnglee lsb pixel order";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Gray,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    // position of the channel's sample within a pixel
+    fn offset(&self, color_type: u8) -> Option<usize> {
+        match (self, color_type) {
+            (Channel::Gray, 0 | 4) => Some(0),
+            (Channel::Alpha, 4) => Some(1),
+            (Channel::Red, 2 | 6) => Some(0),
+            (Channel::Green, 2 | 6) => Some(1),
+            (Channel::Blue, 2 | 6) => Some(2),
+            (Channel::Alpha, 6) => Some(3),
+            _ => None,
+        }
+    }
+}
+
+// channels are given as letters, e.g. "rgb" or "ya" for grey and alpha.
+// Each may only be given once, as a repeated channel would have its bits
+// written over by the next group
+pub fn parse_channels(letters: &str) -> Result<Vec<Channel>> {
+    let mut channels = vec![];
+    for letter in letters.chars() {
+        let channel = match letter.to_ascii_lowercase() {
+            'y' => Channel::Gray,
+            'r' => Channel::Red,
+            'g' => Channel::Green,
+            'b' => Channel::Blue,
+            'a' => Channel::Alpha,
+            other => return Err(anyhow!("Unknown channel '{}', use r, g, b, y or a", other)),
+        };
+        if channels.contains(&channel) {
+            return Err(anyhow!("Channel '{}' is given more than once", letter));
+        }
+        channels.push(channel);
+    }
+    Ok(channels)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsbOptions {
+    pub bits_per_channel: u8,
+    // empty means every colour channel, leaving alpha untouched
+    pub channels: Vec<Channel>,
+    // visits pixels in an order derived from this key instead of
+    // sequentially, and the same key is needed to extract
+    pub order_key: Option<String>,
+}
+
+impl Default for LsbOptions {
+    fn default() -> LsbOptions {
+        LsbOptions {
+            bits_per_channel: 1,
+            channels: vec![],
+            order_key: None,
+        }
+    }
+}
+
+impl LsbOptions {
+    // position of each channel used within a pixel's samples
+    fn offsets(&self, image: &Image) -> Result<Vec<usize>> {
+        if !(1..=8).contains(&self.bits_per_channel) {
+            return Err(anyhow!("Bits per channel must be between 1 and 8"));
+        }
+
+        let color_type = image.header.color_type;
+        if self.channels.is_empty() {
+            let colour = if color_type & 2 != 0 { 3 } else { 1 };
+            return Ok((0..colour).collect());
+        }
+        self.channels
+            .iter()
+            .map(|channel| {
+                channel.offset(color_type).ok_or_else(|| {
+                    anyhow!(
+                        "Channel {:?} is not present in color type {}",
+                        channel,
+                        color_type
+                    )
+                })
+            })
+            .collect()
+    }
+
+    // byte index of every sample used, in embedding order. For 16 bit
+    // samples only the low byte is used
+    fn positions(&self, image: &Image) -> Result<impl Iterator<Item = usize>> {
+        let sample_bytes = image.bytes_per_sample();
+        let pixel_bytes = image.channels() * sample_bytes;
+        let sample_offsets: Vec<usize> = self
+            .offsets(image)?
+            .into_iter()
+            .map(|offset| offset * sample_bytes + sample_bytes - 1)
+            .collect();
+        let per_pixel = sample_offsets.len();
+        Ok(self
+            .pixel_order(image.pixel_count())
+            .flat_map(move |pixel| (0..per_pixel).map(move |i| (pixel, i)))
+            .map(move |(pixel, i)| pixel * pixel_bytes + sample_offsets[i]))
+    }
+
+    fn pixel_order(&self, pixel_count: usize) -> PixelOrder {
+        let rng = self.order_key.as_ref().map(|key| {
+            let mut hasher = Sha256::new();
+            hasher.update(ORDER_DOMAIN);
+            hasher.update(key.as_bytes());
+            ChaCha20Rng::from_seed(hasher.finalize().into())
+        });
+        PixelOrder {
+            rng,
+            moved: HashMap::new(),
+            next: 0,
+            pixel_count,
+        }
+    }
+}
+
+// a Fisher-Yates shuffle run from the front one step at a time, seeded
+// from the key. Only the entries swapped so far are kept, so reading a
+// short payload out of a large image touches only the pixels it needs.
+// Indices are drawn as u64 so the order is the same on every platform
+struct PixelOrder {
+    rng: Option<ChaCha20Rng>,
+    moved: HashMap<usize, usize>,
+    next: usize,
+    pixel_count: usize,
+}
+
+impl Iterator for PixelOrder {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.next >= self.pixel_count {
+            return None;
+        }
+        let current = self.next;
+        self.next += 1;
+        let Some(rng) = &mut self.rng else {
+            return Some(current);
+        };
+
+        let chosen = rng.gen_range(current as u64..self.pixel_count as u64) as usize;
+        let at_current = self.moved.remove(&current).unwrap_or(current);
+        if chosen == current {
+            return Some(at_current);
+        }
+        Some(self.moved.insert(chosen, at_current).unwrap_or(chosen))
+    }
+}
+
+// bytes of payload that fit, after the header
+pub fn capacity(image: &Image, options: &LsbOptions) -> Result<usize> {
+    let samples = image.pixel_count() * options.offsets(image)?.len();
+    let bits = samples * options.bits_per_channel as usize;
+    Ok((bits / 8).saturating_sub(HEADER_LENGTH))
+}
+
+pub fn embed(image: &mut Image, payload: &[u8], options: &LsbOptions) -> Result<()> {
+    let available = capacity(image, options)?;
+    if payload.len() > available {
+        return Err(anyhow!(
+            "Payload of {} bytes does not fit, capacity is {} bytes",
+            payload.len(),
+            available
+        ));
+    }
+
+    let mut message = LSB_MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+
+    let bits = options.bits_per_channel as usize;
+    let mut message_bits = message
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+        .peekable();
+    let mut positions = options.positions(image)?;
+    while message_bits.peek().is_some() {
+        let sample = &mut image.data[positions.next().unwrap()];
+        for shift in (0..bits).rev() {
+            let Some(bit) = message_bits.next() else {
+                break;
+            };
+            *sample = (*sample & !(1 << shift)) | (bit << shift);
+        }
+    }
+    Ok(())
+}
+
+pub fn extract(image: &Image, options: &LsbOptions) -> Result<Vec<u8>> {
+    let available = capacity(image, options)?;
+    let bits = options.bits_per_channel as usize;
+    let mut sample_bits = options.positions(image)?.flat_map(|position| {
+        (0..bits)
+            .rev()
+            .map(move |shift| (image.data[position] >> shift) & 1)
+    });
+    let mut read_bytes = |length: usize| -> Vec<u8> {
+        (0..length)
+            .map(|_| {
+                sample_bits
+                    .by_ref()
+                    .take(8)
+                    .fold(0u8, |byte, bit| (byte << 1) | bit)
+            })
+            .collect()
+    };
+
+    if available == 0 || read_bytes(4) != LSB_MAGIC {
+        return Err(anyhow!("No hidden payload found in pixel data"));
+    }
+    let length = u32::from_be_bytes(read_bytes(4).try_into().unwrap()) as usize;
+    if length > available {
+        return Err(anyhow!("Hidden payload length is larger than the image"));
+    }
+    Ok(read_bytes(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::Png;
+    use std::collections::HashSet;
+
+    const PNG_FILE: &[u8] = include_bytes!("../capy.png");
+
+    fn capy() -> Image {
+        Image::from_png(&Png::try_from(PNG_FILE).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut image = capy();
+        let original = image.clone();
+        let options = LsbOptions::default();
+
+        embed(&mut image, b"hidden in plain sight", &options).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), b"hidden in plain sight");
+
+        // only the lowest bit of each colour sample may change
+        assert!(image
+            .data
+            .iter()
+            .zip(&original.data)
+            .all(|(a, b)| a ^ b <= 1));
+        assert!(image
+            .data
+            .iter()
+            .skip(3)
+            .step_by(4)
+            .eq(original.data.iter().skip(3).step_by(4)));
+    }
+
+    #[test]
+    fn test_bits_and_channels() {
+        let mut image = capy();
+        let options = LsbOptions {
+            bits_per_channel: 3,
+            channels: parse_channels("ga").unwrap(),
+            order_key: None,
+        };
+        let payload: Vec<u8> = (0..=255).collect();
+
+        embed(&mut image, &payload, &options).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), payload);
+        assert!(extract(&image, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = capy();
+        let pixels = image.pixel_count();
+        assert_eq!(
+            capacity(&image, &LsbOptions::default()).unwrap(),
+            pixels * 3 / 8 - HEADER_LENGTH
+        );
+
+        let two_bits = LsbOptions {
+            bits_per_channel: 2,
+            channels: vec![Channel::Alpha],
+            order_key: None,
+        };
+        assert_eq!(
+            capacity(&image, &two_bits).unwrap(),
+            pixels * 2 / 8 - HEADER_LENGTH
+        );
+        assert_eq!(two_bits.positions(&image).unwrap().count(), pixels);
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let mut image = capy();
+        let options = LsbOptions::default();
+        let too_big = vec![0; capacity(&image, &options).unwrap() + 1];
+        assert!(embed(&mut image, &too_big, &options).is_err());
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let image = capy();
+        assert!(parse_channels("rgx").is_err());
+        assert!(parse_channels("rr").is_err());
+        assert!(parse_channels("gRr").is_err());
+        let gray = LsbOptions {
+            bits_per_channel: 1,
+            channels: vec![Channel::Gray],
+            order_key: None,
+        };
+        assert!(capacity(&image, &gray).is_err());
+        let zero_bits = LsbOptions {
+            bits_per_channel: 0,
+            channels: vec![],
+            order_key: None,
+        };
+        assert!(capacity(&image, &zero_bits).is_err());
+    }
+
+    #[test]
+    fn test_clean_image_has_no_payload() {
+        let err = extract(&capy(), &LsbOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "No hidden payload found in pixel data");
+    }
+
+    fn keyed(key: &str) -> LsbOptions {
+        LsbOptions {
+            order_key: Some(key.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_keyed_order_is_fixed() {
+        // pinned so a change to the shuffle is caught, as it would make
+        // existing images unreadable
+        let order: Vec<usize> = keyed("correct horse").pixel_order(1000).collect();
+        assert_eq!(&order[..8], &[269, 574, 335, 652, 351, 13, 914, 664]);
+
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert!(sorted.into_iter().eq(0..1000));
+    }
+
+    #[test]
+    fn test_keyed_order_is_lazy() {
+        let order = keyed("correct horse").pixel_order(usize::MAX);
+        assert_eq!(order.take(1000).collect::<HashSet<_>>().len(), 1000);
+    }
+
+    #[test]
+    fn test_keyed_embedding_is_deterministic() {
+        let options = keyed("correct horse");
+        let mut first = capy();
+        let mut second = capy();
+        embed(&mut first, b"same every time", &options).unwrap();
+        embed(&mut second, b"same every time", &options).unwrap();
+        assert_eq!(first.data, second.data);
+
+        let mut sequential = capy();
+        embed(&mut sequential, b"same every time", &LsbOptions::default()).unwrap();
+        assert_ne!(first.data, sequential.data);
+    }
+
+    #[test]
+    fn test_keyed_extraction_needs_key() {
+        let mut image = capy();
+        embed(&mut image, b"scattered", &keyed("correct horse")).unwrap();
+
+        assert_eq!(
+            extract(&image, &keyed("correct horse")).unwrap(),
+            b"scattered"
+        );
+        assert!(extract(&image, &keyed("battery staple")).is_err());
+        assert!(extract(&image, &LsbOptions::default()).is_err());
+    }
+}
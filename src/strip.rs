@@ -0,0 +1,154 @@
+use clap::ValueEnum;
+
+use crate::chunk::Chunk;
+use crate::known_chunk::KnownChunk;
+
+// colour management chunks, most of which have no typed parser, so they
+// are matched by name
+const COLOUR_TYPES: [&str; 8] = [
+    "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCv", "cLLI",
+];
+// text keywords ImageMagick and others use to carry a copy of the Exif
+// block, GPS tags included
+const EXIF_KEYWORDS: [&str; 2] = ["Raw profile type exif", "Raw profile type APP1"];
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum StripPreset {
+    /// Every ancillary chunk
+    Ancillary,
+    /// tEXt, zTXt and iTXt
+    Text,
+    /// eXIf and text chunks holding raw Exif, where GPS tags live
+    Exif,
+    /// Every ancillary chunk except colour management and tRNS
+    KeepColor,
+    /// Private ancillary chunks
+    Private,
+}
+
+impl StripPreset {
+    fn matches(&self, chunk: &Chunk) -> bool {
+        let chunk_type = chunk.chunk_type().to_string();
+        match self {
+            StripPreset::Ancillary => true,
+            StripPreset::Text => chunk.known_type().is_some_and(|t| t.is_text()),
+            StripPreset::Exif => chunk_type == "eXIf" || is_exif_text(chunk),
+            // tRNS is kept as well, as dropping it changes how the pixels look
+            StripPreset::KeepColor => {
+                !COLOUR_TYPES.contains(&chunk_type.as_str()) && chunk_type != "tRNS"
+            }
+            StripPreset::Private => !chunk.chunk_type().is_public(),
+        }
+    }
+}
+
+fn is_exif_text(chunk: &Chunk) -> bool {
+    let keyword = match chunk.parse_known() {
+        Ok(KnownChunk::Text(text)) => text.keyword,
+        Ok(KnownChunk::Ztxt(ztxt)) => ztxt.keyword,
+        Ok(KnownChunk::Itxt(itxt)) => itxt.keyword,
+        _ => return false,
+    };
+    EXIF_KEYWORDS.contains(&keyword.as_str())
+}
+
+// a chunk is stripped if the preset or the remove list matches it and
+// the keep list doesn't. Critical chunks are never stripped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StripRules {
+    pub preset: Option<StripPreset>,
+    pub remove: Vec<String>,
+    pub keep: Vec<String>,
+}
+
+impl StripRules {
+    pub fn should_strip(&self, chunk: &Chunk) -> bool {
+        if chunk.chunk_type().is_critical() {
+            return false;
+        }
+        let chunk_type = chunk.chunk_type().to_string();
+        if self.keep.contains(&chunk_type) {
+            return false;
+        }
+        self.remove.contains(&chunk_type) || self.preset.is_some_and(|p| p.matches(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use std::str::FromStr;
+
+    const PNG_FILE: &[u8] = include_bytes!("../capy.png");
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()).unwrap()
+    }
+
+    fn stripped_types(png: &Png, rules: &StripRules) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .filter(|c| rules.should_strip(c))
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn rules(preset: StripPreset) -> StripRules {
+        StripRules {
+            preset: Some(preset),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        let mut png = Png::try_from(PNG_FILE).unwrap();
+        png.append_chunk(chunk("eXIf", b"MM\0*"));
+        png.append_chunk(chunk("ruSt", b"private"));
+
+        assert_eq!(
+            stripped_types(&png, &rules(StripPreset::Ancillary)),
+            vec!["gAMA", "cHRM", "bKGD", "tIME", "tEXt", "tEXt", "eXIf", "ruSt"]
+        );
+        assert_eq!(
+            stripped_types(&png, &rules(StripPreset::Text)),
+            vec!["tEXt", "tEXt"]
+        );
+        assert_eq!(
+            stripped_types(&png, &rules(StripPreset::KeepColor)),
+            vec!["bKGD", "tIME", "tEXt", "tEXt", "eXIf", "ruSt"]
+        );
+        assert_eq!(
+            stripped_types(&png, &rules(StripPreset::Private)),
+            vec!["ruSt"]
+        );
+    }
+
+    #[test]
+    fn test_exif_preset() {
+        let exif = rules(StripPreset::Exif);
+        assert!(exif.should_strip(&chunk("eXIf", b"MM\0*")));
+        assert!(exif.should_strip(&chunk("tEXt", b"Raw profile type exif\0\nexif\n")));
+        assert!(!exif.should_strip(&chunk("tEXt", b"Comment\0hello")));
+    }
+
+    #[test]
+    fn test_keep_and_remove_lists() {
+        let png = Png::try_from(PNG_FILE).unwrap();
+        let allow = StripRules {
+            preset: Some(StripPreset::Ancillary),
+            remove: vec![],
+            keep: vec!["gAMA".to_string(), "tEXt".to_string()],
+        };
+        assert_eq!(stripped_types(&png, &allow), vec!["cHRM", "bKGD", "tIME"]);
+
+        let deny = StripRules {
+            preset: None,
+            remove: vec!["tIME".to_string(), "IHDR".to_string()],
+            keep: vec![],
+        };
+        assert_eq!(stripped_types(&png, &deny), vec!["tIME"]);
+    }
+}